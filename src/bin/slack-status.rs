@@ -6,6 +6,7 @@ extern crate log;
 use std::net::IpAddr;
 use std::process::exit;
 
+use chrono::prelude::*;
 use clap::App;
 use console::{Style, style};
 use dialoguer::{theme::ColorfulTheme, Checkboxes, Confirmation, Input, Select};
@@ -14,6 +15,9 @@ use slack_status::*;
 
 type Theme = dialoguer::theme::ColorfulTheme;
 
+/// Radius proposed for geofences of new locations, in meters.
+const DEFAULT_GEOFENCE_RADIUS: f64 = 200.0;
+
 struct Prompt {
     theme: Theme,
 }
@@ -27,7 +31,7 @@ fn main() {
         0 => log::LevelFilter::Error,
         1 => log::LevelFilter::Warn,
         2 => log::LevelFilter::Info,
-        3 | _ => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Debug,
     };
 
     match setup_logger(log_level) {
@@ -80,27 +84,50 @@ fn main() {
 
     // Subcommand reading
     if let Some(submatches) = matches.subcommand_matches("location") {
-        if let Some(_) = submatches.subcommand_matches("list") {
+        if submatches.subcommand_matches("list").is_some() {
             // slack-status location list
             list_locations(&client);
-        } else if let Some(_) = submatches.subcommand_matches("add") {
+        } else if submatches.subcommand_matches("add").is_some() {
             // slack-status location add
             add_location(&prompt, &client, &config, matches.value_of("config"));
-        } else if let Some(_) = submatches.subcommand_matches("rm") {
+        } else if submatches.subcommand_matches("rm").is_some() {
             // slack-status location rm
             rm_location(&prompt, &config, matches.value_of("config"));
-        } else if let Some(_) = submatches.subcommand_matches("ignore") {
+        } else if submatches.subcommand_matches("ignore").is_some() {
             // slack-status location ignore
             ignore_location(&prompt, &client, &config, matches.value_of("config"));
         }
+    } else if let Some(submatches) = matches.subcommand_matches("config") {
+        if submatches.subcommand_matches("check").is_some() {
+            // slack-status config check
+            check_config(&config);
+        }
+    } else if let Some(submatches) = matches.subcommand_matches("ooo") {
+        if submatches.subcommand_matches("list").is_some() {
+            // slack-status ooo list
+            list_ooo(&client);
+        } else if let Some(args) = submatches.subcommand_matches("add") {
+            // slack-status ooo add --from <DATE> --to <DATE>
+            add_ooo(args, &config, matches.value_of("config"));
+        }
+    } else if let Some(submatches) = matches.subcommand_matches("calendar") {
+        if submatches.subcommand_matches("sync").is_some() {
+            // slack-status calendar sync
+            sync_calendar(&client);
+        }
+    } else if let Some(submatches) = matches.subcommand_matches("rules") {
+        if let Some(args) = submatches.subcommand_matches("test") {
+            // slack-status rules test [--at <TIME>] [--ip <IP>]
+            test_rules(args, &client);
+        }
     } else if let Some(submatches) = matches.subcommand_matches("status") {
-        if let Some(_) = submatches.subcommand_matches("get") {
+        if submatches.subcommand_matches("get").is_some() {
             // slack-status status get
             get_status(&client);
-        } else if let Some(_) = submatches.subcommand_matches("set") {
+        } else if submatches.subcommand_matches("set").is_some() {
             // slack-status status set
             set_status(&prompt, &client);
-        } else if let Some(_) = submatches.subcommand_matches("reset-cache") {
+        } else if submatches.subcommand_matches("reset-cache").is_some() {
             // slack-status status reset-cache
             reset_cache();
         }
@@ -141,18 +168,62 @@ fn configuration_wizard(prompt: &Prompt, path: Option<&str>) -> BoxResult<Config
 
 /// Update Slack status based on current location.
 fn status_update(prompt: &Prompt, client: &SlackStatus, non_interactive: bool) {
-    debug!("Requesting public ip...");
-    let ip = match client.get_public_ip() {
-        Ok(ip) => ip,
+    // Out of office, location does not matter.
+    if let Some(status) = client.ooo_status(Utc::now()) {
+        if client.is_ooo_status_set(&status) {
+            println!("{}", style("Out of office, automatic updates are suspended").bold());
+            return;
+        }
+
+        let replacer = gh_emoji::Replacer::new();
+        println!("{}: {}",
+            style("Out-of-office status").bold(),
+            style(replacer.replace_all(&format!("{}", status))).yellow()
+        );
+
+        if non_interactive || Confirmation::with_theme(&prompt.theme)
+            .with_text("Do you want to update your status?")
+            .interact()
+            .unwrap()
+        {
+            debug!("Setting out-of-office status...");
+            match client.set_slack_status(&status, !non_interactive) {
+                Ok(true) => print_slack_status_updated(),
+                Ok(false) => print_no_modification(),
+                Err(e) => panic!("Failed to change status: {:?}", e),
+            };
+        } else {
+            print_no_modification();
+        }
+        return;
+    }
+
+    debug!("Detecting location...");
+    let mut detection = match client.detect() {
+        Ok(d) => d,
+        // Like unknown locations within the grace period.
+        Err(e) if client.in_grace_period() => {
+            warn!("Cannot detect location: {}", e);
+            println!("{}", style("Cannot detect location, previous location status is kept").bold());
+            Detection { ignored: true, ..Default::default() }
+        },
         Err(e) => {
-            error!("Cannot get public IP: {}", e);
+            error!("Cannot detect location: {}", e);
             exit(1);
         },
     };
-    print_ip(&ip);
+    client.apply_grace_period(&mut detection);
+    print_detection(&detection);
+
+    // Status is only switched to locations detected long enough.
+    if non_interactive && !client.stabilize(&mut detection) {
+        println!("{}", style("Current location is not stable yet, previous location is kept").bold());
+    }
 
     debug!("Computing status...");
-    let status = client.status_from(&ip);
+    let now = Utc::now();
+    let meetings = client.meetings(now);
+    let (status, source) = client.status_with(&detection, &meetings, now);
     let replacer = gh_emoji::Replacer::new();
     println!("{}: {}",
         style("Location's status").bold(),
@@ -167,9 +238,21 @@ fn status_update(prompt: &Prompt, client: &SlackStatus, non_interactive: bool) {
     {
         debug!("Updating Slack status...");
         match client.set_slack_status(&status, !non_interactive) {
-            Ok(_) => print_slack_status_updated(),
+            Ok(true) => client.remember_location(&detection, source),
+            Ok(false) => {
+                print_no_modification();
+                return;
+            },
             Err(e) => panic!("Failed to change status: {:?}", e),
         };
+        print_slack_status_updated();
+
+        if let Some(rule) = client.matching_rule(&detection, &meetings, now) {
+            debug!("Applying rule {}...", rule.label());
+            if let Err(e) = client.apply_rule(rule) {
+                error!("Failed to apply rule {}: {}", rule.label(), e);
+            }
+        }
     } else {
         print_no_modification();
     }
@@ -182,26 +265,250 @@ fn list_locations(client: &SlackStatus) {
     for (n, l) in client.config.locations.iter().enumerate() {
         println!(" {}. {}: {} {}",
             style(n + 1).blue(),
-            style(l.ip).cyan(),
-            replacer.replace_all(&format!("{}", l.emoji)),
+            style(l.label()).cyan(),
+            replacer.replace_all(&l.emoji),
             style(&l.text).yellow(),
         );
     }
 }
 
+/// Print the list of out-of-office date ranges.
+fn list_ooo(client: &SlackStatus) {
+    debug!("Listing out-of-office ranges...");
+    let replacer = gh_emoji::Replacer::new();
+    for (n, o) in client.config.ooo.iter().enumerate() {
+        println!(" {}. {} to {}: {} {}",
+            style(n + 1).blue(),
+            style(o.from).cyan(),
+            style(o.to).cyan(),
+            replacer.replace_all(&o.emoji),
+            style(&o.text).yellow(),
+        );
+    }
+}
+
+/// Sync calendar events and print the meetings of the next 24 hours.
+fn sync_calendar(client: &SlackStatus) {
+    debug!("Syncing calendar...");
+    let calendar = match &client.config.calendar {
+        Some(c) => c,
+        None => {
+            error!("No calendar configured.");
+            exit(1);
+        },
+    };
+
+    let ics = match calendar.sync(client.config.timezone()) {
+        Ok(i) => i,
+        Err(e) => {
+            error!("Failed to sync calendar: {}", e);
+            exit(1);
+        },
+    };
+    println!("{}", style("Calendar synced, upcoming meetings:").bold());
+
+    let now = Utc::now();
+    for m in ics.meetings(now, now + chrono::Duration::days(1)) {
+        println!(" {} - {}: {}",
+            style(m.start.with_timezone(&Local).format("%a %H:%M")).cyan(),
+            style(m.end.with_timezone(&Local).format("%H:%M")).cyan(),
+            style(&m.summary).yellow(),
+        );
+    }
+}
+
+/// Print which rules match at some time, from some public IP, and the
+/// resulting status.
+fn test_rules(args: &clap::ArgMatches, client: &SlackStatus) {
+    debug!("Testing rules...");
+    let timezone = client.config.timezone();
+    let now = match args.value_of("at") {
+        Some(at) => match parse_time(at, timezone) {
+            Some(t) => t,
+            None => {
+                error!("Invalid --at time, expected \"YYYY-MM-DD HH:MM\" or \"HH:MM\": {}", at);
+                exit(1);
+            },
+        },
+        None => Utc::now(),
+    };
+
+    let detection = match args.value_of("ip") {
+        Some(ip) => match ip.parse::<IpAddr>() {
+            Ok(ip) => {
+                let mut detection = Detection::default();
+                detector::public_ip::match_ips(client.config, vec![ip], &mut detection);
+                detection
+            },
+            Err(e) => {
+                error!("Invalid --ip address: {}", e);
+                exit(1);
+            },
+        },
+        None => match client.detect() {
+            Ok(d) => d,
+            Err(e) => {
+                error!("Cannot detect location: {}", e);
+                exit(1);
+            },
+        },
+    };
+    print_detection(&detection);
+    println!("{}: {}",
+        style("Time").bold(),
+        style(template::in_timezone(now, timezone).format("%a %Y-%m-%d %H:%M")).cyan()
+    );
+
+    let meetings = client.meetings(now);
+    let signals = client.signals(&detection, &meetings, now);
+    let matching = client.matching_rule(&detection, &meetings, now);
+    for (n, r) in client.config.rules.iter().enumerate() {
+        let result = match r.matches(&signals) {
+            true if matching == Some(r) => style("wins").green(),
+            true => style("matches").green(),
+            false => style("no match").red(),
+        };
+        println!(" {}. {}: {}", style(n + 1).blue(), style(r.label()).cyan(), result);
+    }
+
+    let replacer = gh_emoji::Replacer::new();
+    println!("{}: {}",
+        style("Resulting status").bold(),
+        style(replacer.replace_all(&format!("{}", client.status_with(&detection, &meetings, now).0))).yellow()
+    );
+    if let Some(presence) = matching.and_then(|r| r.presence) {
+        println!("{}: {}", style("Presence").bold(), style(format!("{:?}", presence).to_lowercase()).cyan());
+    }
+    if let Some(minutes) = matching.and_then(|r| r.dnd_minutes) {
+        println!("{}: {}", style("Do Not Disturb").bold(), style(format!("{} minutes", minutes)).cyan());
+    }
+}
+
+/// Parse "YYYY-MM-DD HH:MM", or "HH:MM" today, in the timezone (system
+/// timezone if not set).
+fn parse_time(time: &str, timezone: Option<chrono_tz::Tz>) -> Option<DateTime<Utc>> {
+    let today = template::in_timezone(Utc::now(), timezone).date_naive();
+    let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").ok()
+        .or_else(|| NaiveTime::parse_from_str(time, "%H:%M").ok().map(|t| today.and_time(t)))?;
+
+    match timezone {
+        Some(tz) => tz.from_local_datetime(&time).earliest().map(|t| t.with_timezone(&Utc)),
+        None => Local.from_local_datetime(&time).earliest().map(|t| t.with_timezone(&Utc)),
+    }
+}
+
+/// Add an out-of-office date range.
+fn add_ooo(args: &clap::ArgMatches, old_config: &Config, custom_path: Option<&str>) {
+    debug!("Adding out-of-office range...");
+    let date = |name| match NaiveDate::parse_from_str(args.value_of(name).unwrap(), "%Y-%m-%d") {
+        Ok(d) => d,
+        Err(e) => {
+            error!("Invalid --{} date, expected YYYY-MM-DD: {}", name, e);
+            exit(1);
+        },
+    };
+    let (from, to) = (date("from"), date("to"));
+    if from > to {
+        error!("Out-of-office range ends before it starts.");
+        exit(1);
+    }
+    for name in ["text", "emoji"] {
+        if let Err(e) = args.value_of(name).map_or(Ok(()), |t| Template::parse(t).map(|_| ())) {
+            error!("Invalid --{} template: {}", name, e);
+            exit(1);
+        }
+    }
+
+    let mut config = old_config.clone();
+    config.ooo.push(OutOfOffice::new(
+        from,
+        to,
+        args.value_of("text").map(|t| t.to_string()),
+        args.value_of("emoji").map(|e| e.to_string()),
+    ));
+
+    match config.save(custom_path) {
+        Ok(_) => print_configuration_saved(),
+        Err(e) => {
+            error!("Failed to save configuration file: {}", e);
+            exit(1);
+        },
+    };
+}
+
 /// Add (or replace) status for current location.
 fn add_location(prompt: &Prompt, client: &SlackStatus, old_config: &Config, custom_path: Option<&str>) {
     debug!("Adding current location...");
-    debug!("Requesting public ip...");
-    let ip = match client.get_public_ip() {
-        Ok(ip) => ip,
+    let mut observed = Location::default();
+
+    debug!("Reading Wi-Fi SSID...");
+    observed.ssid = match detector::SsidDetector::new(client.config.proc_root()).current_ssid() {
+        Ok(s) => s,
         Err(e) => {
-            error!("Cannot get public IP: {}", e);
-            exit(1);
+            warn!("Cannot read Wi-Fi SSID: {}", e);
+            None
+        },
+    };
+
+    debug!("Reading gateway MAC address...");
+    observed.gateway_mac = match detector::GatewayMacDetector::new(client.config.proc_root()).gateway_mac() {
+        Ok(m) => m,
+        Err(e) => {
+            warn!("Cannot read gateway MAC address: {}", e);
+            None
+        },
+    };
+
+    debug!("Reading local addresses...");
+    let local = detector::LocalNetworkDetector::new(client.config.vpn_interfaces.clone());
+    observed.local_network = match local.local_addresses() {
+        // Prefer IPv4 networks, IPv6 ones are less often specific to a site.
+        Ok(addresses) => addresses.iter()
+            .find(|a| a.ip.is_ipv4())
+            .or_else(|| addresses.first())
+            .map(|a| a.network),
+        Err(e) => {
+            warn!("Cannot read local addresses: {}", e);
+            None
         },
     };
 
-    let location = match prompt.add_location(ip) {
+    // GPS position is only read if it is used to detect location.
+    if client.config.detectors.iter().any(|d| d == detector::GPS) {
+        debug!("Reading GPS position...");
+        observed.geofence = match detector::GpsDetector::new(client.config.gpsd_address()).position() {
+            Ok(p) => p.map(|p| Geofence {
+                latitude: p.latitude,
+                longitude: p.longitude,
+                radius: DEFAULT_GEOFENCE_RADIUS,
+            }),
+            Err(e) => {
+                warn!("Cannot read GPS position: {}", e);
+                None
+            },
+        };
+    }
+
+    // Public IP is only requested if it is used to detect location.
+    if client.config.detectors.iter().any(|d| d == detector::PUBLIC_IP) {
+        debug!("Requesting public ip...");
+        let ips = match client.get_public_ips() {
+            Ok(ips) => ips,
+            Err(e) => {
+                error!("Cannot get public IP: {}", e);
+                exit(1);
+            },
+        };
+        observed.ip = ips.iter().find(|ip| ip.is_ipv4()).map(|ip| Network::from(*ip));
+        observed.ip6 = ips.iter().find(|ip| ip.is_ipv6()).map(|ip| Network::from(*ip));
+    }
+
+    if observed.has_no_key() {
+        error!("Cannot identify current location: no public IP, Wi-Fi SSID, gateway MAC address, local network nor GPS position.");
+        exit(1);
+    }
+
+    let location = match prompt.add_location(observed) {
         Ok(l) => match l {
             Some(l) => l,
             None => exit(1),
@@ -212,8 +519,8 @@ fn add_location(prompt: &Prompt, client: &SlackStatus, old_config: &Config, cust
     let replacer = gh_emoji::Replacer::new();
     println!("{}: {} => {} {}",
         style("New location status").bold(),
-        style(location.ip).cyan(),
-        replacer.replace_all(&format!("{}", location.emoji)),
+        style(location.label()).cyan(),
+        replacer.replace_all(&location.emoji),
         style(&location.text).yellow(),
     );
 
@@ -225,8 +532,8 @@ fn add_location(prompt: &Prompt, client: &SlackStatus, old_config: &Config, cust
         let mut config = old_config.clone();
         // Remove current status for this location, if any.
         config.locations = old_config.locations.iter()
-            .filter(|l| l.ip != ip)
-            .map(|l| l.clone()).collect();
+            .filter(|l| l.keys() != location.keys())
+            .cloned().collect();
 
         // Add new status for this location.
         config.locations.push(location);
@@ -263,8 +570,8 @@ fn rm_location(prompt: &Prompt, old_config: &Config, custom_path: Option<&str>)
         println!("{}", style("You selected these locations to be removed:").bold());
         for s in selections {
             println!("  {} => {} {}",
-                style(checkboxes[s].ip).cyan(),
-                replacer.replace_all(&format!("{}", checkboxes[s].emoji)),
+                style(checkboxes[s].label()).cyan(),
+                replacer.replace_all(&checkboxes[s].emoji),
                 style(&checkboxes[s].text).yellow(),
             );
             tbr.push(&checkboxes[s]);
@@ -279,8 +586,7 @@ fn rm_location(prompt: &Prompt, old_config: &Config, custom_path: Option<&str>)
 
             config.locations = old_config.locations.iter()
                 .filter(|l| !tbr.iter()
-                    .any(|s| s == l))
-                .map(|l| l.clone()).collect();
+                    .any(|s| s == l)).cloned().collect();
 
             match config.save(custom_path) {
                 Ok(_) => print_configuration_saved(),
@@ -299,15 +605,17 @@ fn rm_location(prompt: &Prompt, old_config: &Config, custom_path: Option<&str>)
 fn ignore_location(prompt: &Prompt, client: &SlackStatus, old_config: &Config, custom_path: Option<&str>) {
     debug!("Ignoring current location...");
     debug!("Requesting public ip...");
-    let ip = match client.get_public_ip() {
-        Ok(ip) => ip,
+    let ips = match client.get_public_ips() {
+        Ok(ips) => ips,
         Err(e) => {
             error!("Cannot get public IP: {}", e);
             exit(1);
         },
     };
 
-    print_ip(&ip);
+    for ip in &ips {
+        print_ip(ip);
+    }
 
     if Confirmation::with_theme(&prompt.theme)
         .with_text("Ignore this location to set status?")
@@ -315,7 +623,12 @@ fn ignore_location(prompt: &Prompt, client: &SlackStatus, old_config: &Config, c
         .unwrap()
     {
         let mut config = old_config.clone();
-        config.ignore_ips.push(ip);
+        for ip in ips {
+            match prompt.network(ip) {
+                Ok(n) => config.ignore_ips.push(n),
+                Err(_) => exit(1),
+            };
+        }
 
         match config.save(custom_path) {
             Ok(_) => print_configuration_saved(),
@@ -329,11 +642,32 @@ fn ignore_location(prompt: &Prompt, client: &SlackStatus, old_config: &Config, c
     }
 }
 
+/// Check configuration consistency.
+fn check_config(config: &Config) {
+    debug!("Checking configuration...");
+    match config.check() {
+        Ok(_) => {
+            let replacer = gh_emoji::Replacer::new();
+            println!("{}",
+                style(
+                    replacer.replace_all(":heavy_check_mark: Configuration is valid")
+                )
+                .bold()
+                .green()
+            );
+        },
+        Err(e) => {
+            println!("{}", style(e).red());
+            exit(1);
+        },
+    }
+}
+
 /// Manually set current Slack Status.
 fn set_status(prompt: &Prompt, client: &SlackStatus) {
     debug!("Manually set status...");
 
-    let status = match prompt.status(":house_with_garden:", "working remotely") {
+    let status = match prompt.status(":house_with_garden:", "working remotely", false) {
         Ok(s) => match s {
             Some(s) => s,
             None => exit(1),
@@ -375,7 +709,7 @@ fn get_status(client: &SlackStatus) {
 
     let replacer = gh_emoji::Replacer::new();
     println!("{} {}",
-        replacer.replace_all(&format!("{}", status.emoji)),
+        replacer.replace_all(&status.emoji),
         style(status.text).yellow(),
     );
 }
@@ -410,13 +744,13 @@ impl Prompt {
             .with_prompt("Slack App token")
             .interact()?;
 
-        let ip_request_address = Input::with_theme(&self.theme)
+        let ip_request_address: String = Input::with_theme(&self.theme)
             .with_prompt("Where do you want to request your public IP address?")
-            .default("https://ip.clara.net".parse().unwrap())
+            .default(ip_lookup::DEFAULT_IP_SERVICE.parse().unwrap())
             .interact()?;
 
         let mut config = Config::with(token);
-        config.ip_request_address = Some(ip_request_address);
+        config.ip_request_address = vec![IpService::Url(ip_request_address)];
         Ok(Some(config))
     }
 
@@ -431,7 +765,7 @@ impl Prompt {
             return Ok(None);
         }
 
-        let status = match self.status(":mountain_railway:", "commuting") {
+        let status = match self.status(":mountain_railway:", "commuting", true) {
             Ok(s) => match s {
                 Some(s) => s,
                 None => exit(1),
@@ -444,15 +778,16 @@ impl Prompt {
             text: status.text,
             emoji: status.emoji,
             expire_after_hours: status.expire_after_hours,
+            expiration: None,
         });
         Ok(Some(new_config))
     }
 
     /// Prompt for setup location.
-    fn add_location(&self, ip: IpAddr) -> BoxResult<Option<Location>> {
+    fn add_location(&self, observed: Location) -> BoxResult<Option<Location>> {
         println!("{}: {}",
-            style("Current location's public IP").bold(),
-            style(ip).cyan()
+            style("Current location").bold(),
+            style(observed.keys()).cyan()
         );
 
         if !Confirmation::with_theme(&self.theme)
@@ -462,7 +797,7 @@ impl Prompt {
             return Ok(None);
         }
 
-        let status = match self.status(":house_with_garden:", "working remotely") {
+        let status = match self.status(":house_with_garden:", "working remotely", true) {
             Ok(s) => match s {
                 Some(s) => s,
                 None => exit(1),
@@ -470,24 +805,125 @@ impl Prompt {
             Err(_) => exit(1),
         };
 
+        // Identify location by some of the observed keys.
+        let mut keys = Vec::<(&str, fn(&mut Location))>::new();
+        if observed.ip.is_some() {
+            keys.push(("public IPv4", |l| l.ip = None));
+        }
+        if observed.ip6.is_some() {
+            keys.push(("public IPv6", |l| l.ip6 = None));
+        }
+        if observed.ssid.is_some() {
+            keys.push(("Wi-Fi SSID", |l| l.ssid = None));
+        }
+        if observed.gateway_mac.is_some() {
+            keys.push(("gateway MAC address", |l| l.gateway_mac = None));
+        }
+        if observed.local_network.is_some() {
+            keys.push(("local network", |l| l.local_network = None));
+        }
+        if observed.geofence.is_some() {
+            keys.push(("GPS position", |l| l.geofence = None));
+        }
+
+        let mut location = observed;
+        if keys.len() > 1 {
+            let items: Vec<(&str, bool)> = keys.iter().map(|(k, _)| (*k, true)).collect();
+            let selections = Checkboxes::with_theme(&self.theme)
+                .with_prompt("Identify this location by")
+                .items_checked(&items)
+                .interact()?;
+
+            if selections.is_empty() {
+                return Ok(None);
+            }
+
+            for (i, (_, clear)) in keys.iter().enumerate() {
+                if !selections.contains(&i) {
+                    clear(&mut location);
+                }
+            }
+        }
+
+        if let Some(ip) = location.ip {
+            location.ip = match self.network(ip.addr()) {
+                Ok(n) => Some(n),
+                Err(_) => exit(1),
+            };
+        }
+        if let Some(ip) = location.ip6 {
+            location.ip6 = match self.network(ip.addr()) {
+                Ok(n) => Some(n),
+                Err(_) => exit(1),
+            };
+        }
+
+        if let Some(geofence) = location.geofence {
+            let radius: f64 = Input::with_theme(&self.theme)
+                .with_prompt("Geofence radius (meters)")
+                .default(geofence.radius)
+                .interact()?;
+            location.geofence = Some(Geofence { radius, ..geofence });
+        }
+
+        let name: String = Input::with_theme(&self.theme)
+            .with_prompt("Location name (optional)")
+            .allow_empty(true)
+            .interact()?;
+
         Ok(Some(Location {
-            ip: ip,
+            name: if name.is_empty() { None } else { Some(name) },
             text: status.text,
             emoji: status.emoji,
             expire_after_hours: status.expire_after_hours,
+            ..location
         }))
     }
 
-    /// Prompt for status.
-    fn status(&self, default_emoji: &str, default_text: &str) -> BoxResult<Option<StatusConfig>> {
+    /// Prompt for the network to save, either the IP address alone or its
+    /// prefix, useful when public IPs rotate in a pool of addresses.
+    fn network(&self, ip: IpAddr) -> BoxResult<Network> {
+        let max_prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+
+        if !Confirmation::with_theme(&self.theme)
+            .with_text("Do you want to save a network prefix instead of this address only?")
+            .default(ip.is_ipv6())
+            .interact()?
+        {
+            return Ok(Network::from(ip));
+        }
+
+        loop {
+            let prefix_len: u8 = Input::with_theme(&self.theme)
+                .with_prompt("Prefix length")
+                .default(if ip.is_ipv4() { 24 } else { 64 })
+                .interact()?;
+
+            if prefix_len <= max_prefix_len {
+                return Ok(Network::new(ip, prefix_len)?);
+            }
+            println!("{}", style(format!("Prefix length must not exceed {}", max_prefix_len)).red());
+        }
+    }
+
+    /// Prompt for status, validated as a template if the status is rendered
+    /// (location and default statuses), set as is otherwise.
+    fn status(&self, default_emoji: &str, default_text: &str, template: bool) -> BoxResult<Option<StatusConfig>> {
+        let validator = move |input: &str| match template {
+            true => is_template(input),
+            false => Ok(()),
+        };
+
         let emoji = Input::with_theme(&self.theme)
             .with_prompt("emoji")
             .default(default_emoji.parse().unwrap())
+            .validate_with(validator)
             .interact()?;
 
         let text = Input::with_theme(&self.theme)
             .with_prompt("status")
             .default(default_text.parse().unwrap())
+            .validate_with(validator)
             .interact()?;
 
         let expires = Select::with_theme(&self.theme)
@@ -499,17 +935,23 @@ impl Prompt {
             .interact()?;
 
         Ok(Some(StatusConfig {
-            text: text,
-            emoji: emoji,
+            text,
+            emoji,
             expire_after_hours: match expires {
                 0 => Some(1),
                 1 => Some(24),
                 _ => None,
             },
+            expiration: None,
         }))
     }
 }
 
+/// Prompt validator of status templates, see `Template`.
+fn is_template(input: &str) -> Result<(), String> {
+    Template::parse(input).map(|_| ())
+}
+
 /// Setup logger.
 fn setup_logger(log_level: log::LevelFilter) -> Result<(), fern::InitError> {
     fern::Dispatch::new()
@@ -535,6 +977,65 @@ fn print_ip(ip: &IpAddr) {
     );
 }
 
+fn print_detection(detection: &Detection) {
+    for ip in &detection.ips {
+        print_ip(ip);
+    }
+    if let Some(ssid) = &detection.ssid {
+        print_ssid(ssid);
+    }
+    if let Some(mac) = &detection.gateway_mac {
+        println!("{}: {}",
+            style("Current gateway MAC address").bold(),
+            style(mac).cyan()
+        );
+    }
+    if !detection.local_ips.is_empty() {
+        let ips: Vec<String> = detection.local_ips.iter().map(|ip| ip.to_string()).collect();
+        println!("{}: {}",
+            style("Current local addresses").bold(),
+            style(ips.join(", ")).cyan()
+        );
+    }
+    if let Some(place) = &detection.place {
+        println!("{}: {}",
+            style("Current public IP location").bold(),
+            style(place).cyan()
+        );
+    }
+    if let Some(position) = &detection.position {
+        println!("{}: {}",
+            style("Current GPS position").bold(),
+            style(position).cyan()
+        );
+    }
+    if let Some(vpn) = &detection.vpn {
+        println!("{}: {}",
+            style("VPN interface up").bold(),
+            style(vpn).cyan()
+        );
+    }
+
+    match (&detection.location, &detection.detector) {
+        (Some(l), Some(d)) => println!("{}: {} (from {})",
+            style("Current location").bold(),
+            style(l.label()).cyan(),
+            d,
+        ),
+        _ if detection.ignored => println!("{}",
+            style("Current location is ignored, previous status is kept").bold(),
+        ),
+        _ => println!("{}", style("Current location is unknown").bold()),
+    }
+}
+
+fn print_ssid(ssid: &str) {
+    println!("{}: {}",
+        style("Current Wi-Fi SSID").bold(),
+        style(ssid).cyan()
+    );
+}
+
 fn print_no_modification() {
     println!("{}", style("No modification have been performed.").yellow());
 }
@@ -4,24 +4,49 @@ use std::fs::File;
 use std::error::Error;
 use std::fs::create_dir_all;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use directories::ProjectDirs;
 
 type BoxResult<T> = Result<T,Box<dyn Error>>;
 
 /// Slack Status, as sent to the API.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct StatusCache {
     pub text: String,
     pub emoji: String,
     pub expiration: i64,
 }
 
+/// Location detected but not stable yet, see `Stability`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PendingLocation {
+    pub location: String,
+    /// Timestamp of the first detection.
+    pub since: i64,
+    /// Number of consecutive detections.
+    pub detections: u32,
+}
+
 /// Cache status and keep track if it was manually set.
-#[derive(Serialize, Deserialize, Clone)]
+///
+/// The location status is set for (the previous location on next run), the
+/// location waiting to be stable and when a known location was last seen are
+/// kept too.
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Cache {
     pub status: StatusCache,
     pub manually_set: bool,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub pending: Option<PendingLocation>,
+    /// Status is set by a transition from the previous location, it is kept
+    /// until it expires or location changes.
+    #[serde(default)]
+    pub transition: bool,
+    /// Timestamp of the last detection of a known location.
+    #[serde(default)]
+    pub last_seen: Option<i64>,
 }
 
 impl Cache {
@@ -32,6 +57,12 @@ impl Cache {
     /// * Mac: /Users/Alice/Library/Caches/com.nsd.slack-status/status.json
     /// * Windows: C:\Users\Alice\AppData\Roaming\nsd\slack-status\cache\status.json
     fn get_file_path() -> Option<PathBuf> {
+        Cache::path("status.json")
+    }
+
+    /// Get the path of a file in the cache directory, such as mirrored
+    /// calendars, creating the directory if needed.
+    pub fn path(file_name: &str) -> Option<PathBuf> {
         // Default OS location configuration path.
         if let Some(proj_dirs) = ProjectDirs::from("com", "nsd", "slack-status") {
             let cache_dir = proj_dirs.cache_dir();
@@ -42,7 +73,7 @@ impl Cache {
                 create_dir_all(cache_dir.to_str().unwrap()).unwrap();
             }
 
-            Some(cache_dir.to_path_buf().join("status.json"))
+            Some(cache_dir.to_path_buf().join(file_name))
         } else {
             warn!("Cannot find application cache directory.");
             None
@@ -51,57 +82,65 @@ impl Cache {
 
     /// Read cache file from default OS location.
     pub fn read() -> BoxResult<Option<Cache>> {
-        if let Some(cache_file_path) = Cache::get_file_path() {
-            match File::open(cache_file_path) {
-                Ok(mut f) => {
-                    let mut contents = String::new();
-                    f.read_to_string(&mut contents)
-                        .expect("something went wrong reading the file");
-
-                    let cache = match serde_json::from_str(contents.as_str()) {
-                        Ok(c) => c,
-                        Err(e) => bail!("Deserialization error: {}", e),
-                    };
-
-                    Ok(Some(cache))
-                }
-                Err(e) => {
-                    warn!("Cannot read cache directory: {}.", e);
-                    Ok(None)
-                }
+        match Cache::get_file_path() {
+            Some(cache_file_path) => Cache::read_from(&cache_file_path),
+            None => Ok(None),
+        }
+    }
+
+    /// Read cache file at this path.
+    pub fn read_from(cache_file_path: &Path) -> BoxResult<Option<Cache>> {
+        match File::open(cache_file_path) {
+            Ok(mut f) => {
+                let mut contents = String::new();
+                f.read_to_string(&mut contents)
+                    .expect("something went wrong reading the file");
+
+                let cache = match serde_json::from_str(contents.as_str()) {
+                    Ok(c) => c,
+                    Err(e) => bail!("Deserialization error: {}", e),
+                };
+
+                Ok(Some(cache))
+            }
+            Err(e) => {
+                warn!("Cannot read cache directory: {}.", e);
+                Ok(None)
             }
-        } else {
-            Ok(None)
         }
     }
 
     /// Save cache file at default OS location.
     pub fn save(&self) -> BoxResult<()> {
-        if let Some(cache_file_path) = Cache::get_file_path() {
-            match File::create(cache_file_path) {
-                Ok(mut f) => {
-                    debug!("Cache file: {:#?}", f);
-
-                    let cache = match serde_json::to_string(self) {
-                        Ok(c) => c,
-                        Err(e) => bail!("Serialization error: {}", e),
-                    };
-                    debug!("Cache file content: {}", cache);
-
-                    match f.write_all(cache.as_bytes()) {
-                        Ok(_) => {
-                            info!("Cache file saved");
-                            Ok(())
-                        },
-                        Err(e) => bail!(e),
-                    }
-                }
-                Err(e) => {
-                    bail!("Cannot read cache directory: {}.", e)
+        match Cache::get_file_path() {
+            Some(cache_file_path) => self.save_to(&cache_file_path),
+            None => bail!("Cannot find application cache directory."),
+        }
+    }
+
+    /// Save cache file at this path.
+    pub fn save_to(&self, cache_file_path: &Path) -> BoxResult<()> {
+        match File::create(cache_file_path) {
+            Ok(mut f) => {
+                debug!("Cache file: {:#?}", f);
+
+                let cache = match serde_json::to_string(self) {
+                    Ok(c) => c,
+                    Err(e) => bail!("Serialization error: {}", e),
+                };
+                debug!("Cache file content: {}", cache);
+
+                match f.write_all(cache.as_bytes()) {
+                    Ok(_) => {
+                        info!("Cache file saved");
+                        Ok(())
+                    },
+                    Err(e) => bail!(e),
                 }
             }
-        } else {
-            bail!("Cannot find application cache directory.");
+            Err(e) => {
+                bail!("Cannot read cache directory: {}.", e)
+            }
         }
     }
 
@@ -0,0 +1,126 @@
+use std::time::Duration as StdDuration;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use reqwest::blocking::Client;
+use reqwest::Method;
+
+use super::BoxResult;
+
+/// Timeout of CalDAV requests.
+const TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+/// CalDAV XML namespace.
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+
+/// Calendar on a CalDAV server:
+///
+/// * url: URL of the calendar collection, such as
+///   `https://dav.example.com/calendars/alice/work/`.
+/// * username, password: Credentials for basic authentication.
+/// * token: Token for bearer authentication, instead of a username and a
+///   password.
+/// * days: Upcoming days of events to fetch (7 by default).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CalDav {
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    pub days: Option<i64>,
+}
+
+impl CalDav {
+    /// Fetch events from the day before until `days` after, as an ICS
+    /// calendar.
+    pub fn sync(&self, now: DateTime<Utc>) -> BoxResult<String> {
+        self.report(now - Duration::days(1), now + Duration::days(self.days.unwrap_or(7)))
+    }
+
+    /// Fetch events occurring between these times with a `calendar-query`
+    /// REPORT. Recurring events are fetched whole, with their recurrence
+    /// rules.
+    pub fn report(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> BoxResult<String> {
+        debug!("Requesting events from {} to {} to {}...", start, end, self.url);
+
+        let client = Client::builder()
+            .timeout(TIMEOUT)
+            .build()?;
+        let mut request = client.request(Method::from_bytes(b"REPORT")?, self.url.as_str())
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml; charset=utf-8")
+            .body(query(start, end));
+        request = match (&self.token, &self.username) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some(username)) => request.basic_auth(username, self.password.as_ref()),
+            (None, None) => request,
+        };
+
+        let resp = match request.send() {
+            Ok(r) => r,
+            Err(e) => bail!(format!("Request error: {}", e)),
+        };
+
+        if !resp.status().is_success() {
+            bail!(format!("Request error, status is: {}", resp.status()));
+        }
+
+        match resp.text() {
+            Ok(body) => parse_multistatus(&body),
+            Err(e) => bail!(format!("Cannot read response: {}", e)),
+        }
+    }
+
+    /// Check the CalDAV settings, the server is only asked when used.
+    pub fn check(&self) -> Result<(), String> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(format!("invalid CalDAV URL: {}", self.url));
+        }
+        if self.token.is_some() && (self.username.is_some() || self.password.is_some()) {
+            return Err("either a CalDAV token or a username and password can be set, not both".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Body of a `calendar-query` REPORT for events overlapping a time range.
+fn query(start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    format!(r#"<?xml version="1.0" encoding="utf-8" ?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="{}">
+  <D:prop>
+    <C:calendar-data/>
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        <C:time-range start="{}" end="{}"/>
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>
+"#, CALDAV, start.format("%Y%m%dT%H%M%SZ"), end.format("%Y%m%dT%H%M%SZ"))
+}
+
+/// Read the calendar objects of a `multistatus` answer, joined in an ICS
+/// calendar.
+pub fn parse_multistatus(body: &str) -> BoxResult<String> {
+    let doc = match roxmltree::Document::parse(body) {
+        Ok(d) => d,
+        Err(e) => bail!("Invalid CalDAV answer: {}", e),
+    };
+
+    let mut ics = String::new();
+    for node in doc.descendants().filter(|n| n.has_tag_name((CALDAV, "calendar-data"))) {
+        let data: String = node.children().filter_map(|c| c.text()).collect();
+        if !data.trim().is_empty() {
+            ics.push_str(data.trim());
+            ics.push_str("\r\n");
+        }
+    }
+
+    if ics.is_empty() {
+        // No events.
+        ics.push_str("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nEND:VCALENDAR\r\n");
+    }
+    Ok(ics)
+}
@@ -0,0 +1,615 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration as StdDuration;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono_tz::Tz;
+use reqwest::blocking::Client;
+use rrule::{Frequency, RRule, RRuleSet, Unvalidated};
+
+use super::cache::Cache;
+use super::caldav::CalDav;
+use super::config::StatusConfig;
+use crate::BoxResult;
+
+/// Timeout of ICS feeds requests.
+const FEED_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+/// Occurrences of a recurring event returned for a lookup, at most.
+pub const MAX_OCCURRENCES: u16 = 10_000;
+
+/// Calendar to set status during meetings:
+///
+/// * ics: Path of an ICS file, or URL (`http://` or `https://`) of an ICS feed
+///   mirrored in the cache directory.
+/// * caldav: Calendar on a CalDAV server instead, its events are synced in the
+///   cache directory, see `CalDav`.
+/// * refresh_minutes: How often the ICS feed or CalDAV events are synced again
+///   (15 by default), the local copy is used in between and when it fails.
+/// * text: Status text during meetings ("In a meeting" by default).
+/// * emoji: Status emoji during meetings (":spiral_calendar_pad:" by default).
+///
+/// Every event with a start and end time is a meeting, all-day, free
+/// (transparent) and cancelled events are not. Times without timezone are in
+/// the schedule timezone (system timezone if not set).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Calendar {
+    pub ics: Option<String>,
+    pub caldav: Option<CalDav>,
+    pub refresh_minutes: Option<u64>,
+    #[serde(default = "default_text")]
+    pub text: String,
+    #[serde(default = "default_emoji")]
+    pub emoji: String,
+}
+
+fn default_text() -> String {
+    "In a meeting".to_string()
+}
+
+fn default_emoji() -> String {
+    ":spiral_calendar_pad:".to_string()
+}
+
+/// Occurrence of a calendar event.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Meeting {
+    pub summary: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Events read from an ICS calendar.
+pub struct Ics {
+    events: Vec<Event>,
+}
+
+/// Meeting event, occurring once or following recurrence rules.
+struct Event {
+    summary: String,
+    start: DateTime<rrule::Tz>,
+    duration: Duration,
+    recurrence: Option<Recurrence>,
+}
+
+/// Recurrence of an event: rules, added dates and excluded dates.
+struct Recurrence {
+    rules: Vec<RRule<Unvalidated>>,
+    rdates: Vec<DateTime<rrule::Tz>>,
+    exdates: Vec<DateTime<rrule::Tz>>,
+}
+
+/// Content line of a calendar, such as
+/// `DTSTART;TZID=Europe/Paris:20261016T100000`.
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+/// Meeting going on at this time among these meetings, the one ending last
+/// if several are.
+pub fn meeting_at(meetings: &[Meeting], now: DateTime<Utc>) -> Option<&Meeting> {
+    meetings.iter()
+        .filter(|m| m.start <= now && m.end > now)
+        .max_by_key(|m| m.end)
+}
+
+impl Calendar {
+    /// Status of the meeting going on at this time among these meetings, if
+    /// any, expiring when the meeting ends.
+    pub fn status_at(&self, meetings: &[Meeting], now: DateTime<Utc>) -> Option<StatusConfig> {
+        let meeting = meeting_at(meetings, now)?;
+        debug!("In meeting \"{}\" until {}", meeting.summary, meeting.end);
+
+        Some(StatusConfig {
+            text: self.text.clone(),
+            emoji: self.emoji.clone(),
+            expire_after_hours: None,
+            expiration: Some(meeting.end.timestamp()),
+        })
+    }
+
+    /// Read the calendar events.
+    pub fn load(&self, timezone: Option<Tz>) -> BoxResult<Ics> {
+        Ics::parse(&self.read()?, timezone)
+    }
+
+    /// Download the ICS feed or CalDAV events now, and keep a local copy.
+    pub fn sync(&self, timezone: Option<Tz>) -> BoxResult<Ics> {
+        Ics::parse(&self.fetch()?, timezone)
+    }
+
+    /// Check the calendar settings, local files must be readable, feeds and
+    /// CalDAV servers are only asked when used.
+    pub fn check(&self, timezone: Option<Tz>) -> Result<(), String> {
+        match (&self.ics, &self.caldav) {
+            (Some(_), Some(_)) | (None, None) => Err("either ics or caldav must be set".to_string()),
+            (Some(ics), None) if !is_url(ics) => match self.load(timezone) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.to_string()),
+            },
+            (_, Some(caldav)) => caldav.check(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the ICS file, or the local copy of the ICS feed or CalDAV events
+    /// once synced again if it is too old.
+    fn read(&self) -> BoxResult<String> {
+        if let Some(path) = self.ics.as_ref().filter(|i| !is_url(i)) {
+            return match fs::read_to_string(path) {
+                Ok(s) => Ok(s),
+                Err(e) => bail!("Cannot read calendar {}: {}", path, e),
+            };
+        }
+
+        let copy = self.copy_path()?;
+        let refresh = StdDuration::from_secs(60 * self.refresh_minutes.unwrap_or(15));
+        let fresh = fs::metadata(&copy).and_then(|m| m.modified()).ok()
+            .and_then(|t| t.elapsed().ok())
+            .is_some_and(|age| age < refresh);
+
+        if !fresh {
+            match self.fetch() {
+                Ok(body) => return Ok(body),
+                Err(e) => warn!("Cannot sync calendar, using local copy: {}", e),
+            }
+        }
+
+        match fs::read_to_string(&copy) {
+            Ok(s) => Ok(s),
+            Err(e) => bail!("Cannot read local copy of calendar {:?}: {}", copy, e),
+        }
+    }
+
+    /// Download the ICS feed or CalDAV events, and keep a local copy.
+    fn fetch(&self) -> BoxResult<String> {
+        let body = match (&self.ics, &self.caldav) {
+            (Some(url), _) if is_url(url) => download(url)?,
+            (None, Some(caldav)) => caldav.sync(Utc::now())?,
+            _ => bail!("Calendar is a local file, there is nothing to sync"),
+        };
+
+        let copy = self.copy_path()?;
+        if let Err(e) = fs::write(&copy, &body) {
+            warn!("Cannot save local copy of calendar {:?}: {}", copy, e);
+        }
+        Ok(body)
+    }
+
+    fn copy_path(&self) -> BoxResult<PathBuf> {
+        let name = if self.caldav.is_some() { "caldav.ics" } else { "calendar.ics" };
+        match Cache::path(name) {
+            Some(p) => Ok(p),
+            None => bail!("Cannot find application cache directory."),
+        }
+    }
+}
+
+fn is_url(ics: &str) -> bool {
+    ics.starts_with("http://") || ics.starts_with("https://")
+}
+
+/// Download an ICS feed.
+fn download(url: &str) -> BoxResult<String> {
+    debug!("Downloading calendar {}...", url);
+
+    let client = Client::builder()
+        .timeout(FEED_TIMEOUT)
+        .build()?;
+    let resp = match client.get(url).send() {
+        Ok(r) => r,
+        Err(e) => bail!(format!("Request error: {}", e)),
+    };
+
+    if !resp.status().is_success() {
+        bail!(format!("Request error, status is: {}", resp.status()));
+    }
+
+    match resp.text() {
+        Ok(b) => Ok(b),
+        Err(e) => bail!(format!("Cannot read response: {}", e)),
+    }
+}
+
+impl Ics {
+    /// Parse an ICS calendar, times without timezone are in this timezone
+    /// (system timezone if not set).
+    ///
+    /// Invalid events are skipped with a warning. Modified occurrences of
+    /// recurring events (with a `RECURRENCE-ID`) replace the original ones.
+    pub fn parse(text: &str, timezone: Option<Tz>) -> BoxResult<Ics> {
+        let timezone = timezone.map_or(rrule::Tz::LOCAL, rrule::Tz::Tz);
+
+        // Properties of each event, nested components such as alarms are
+        // skipped.
+        let mut components = Vec::<Vec<Property>>::new();
+        let mut stack = Vec::<String>::new();
+        for line in unfold(text) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let property = match Property::parse(&line) {
+                Some(p) => p,
+                None => bail!("Invalid calendar line: {}", line),
+            };
+
+            match property.name.as_str() {
+                "BEGIN" => {
+                    let name = property.value.to_uppercase();
+                    if name == "VEVENT" {
+                        components.push(Vec::new());
+                    }
+                    stack.push(name);
+                },
+                "END" => {
+                    stack.pop();
+                },
+                _ => if stack.last().is_some_and(|c| c == "VEVENT") {
+                    if let Some(c) = components.last_mut() {
+                        c.push(property);
+                    }
+                },
+            }
+        }
+        if !text.trim_start().to_uppercase().starts_with("BEGIN:VCALENDAR") {
+            bail!("Not an ICS calendar");
+        }
+
+        // Occurrences replaced by modified ones, by event UID.
+        let mut replaced = HashMap::<String, Vec<DateTime<rrule::Tz>>>::new();
+        for props in &components {
+            if let (Some(uid), Some(id)) = (find(props, "UID"), find(props, "RECURRENCE-ID")) {
+                match date_time(&id.value, id.param("TZID"), timezone) {
+                    Ok(Some(d)) => replaced.entry(uid.value.clone()).or_default().push(d),
+                    Ok(None) => (),
+                    Err(e) => warn!("{}", e),
+                }
+            }
+        }
+
+        let mut events = Vec::new();
+        for props in &components {
+            let summary = find(props, "SUMMARY").map_or("", |p| p.value.as_str());
+            match Event::parse(props, timezone, &replaced) {
+                Ok(Some(e)) => events.push(e),
+                Ok(None) => debug!("Event \"{}\" is not a meeting", summary),
+                Err(e) => warn!("Skipping event \"{}\": {}", summary, e),
+            }
+        }
+
+        Ok(Ics { events })
+    }
+
+    /// Meeting going on at this time, the one ending last if several are.
+    pub fn meeting_at(&self, now: DateTime<Utc>) -> Option<Meeting> {
+        meeting_at(&self.meetings(now, now), now).cloned()
+    }
+
+    /// Meetings going on between these times, by start time.
+    pub fn meetings(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Meeting> {
+        let mut meetings: Vec<Meeting> = self.events.iter()
+            .flat_map(|e| e.occurrences(from, to))
+            .collect();
+        meetings.sort_by_key(|m| m.start);
+        meetings
+    }
+}
+
+impl Event {
+    /// Read an event from its properties, `None` if it is not a meeting.
+    fn parse(
+        props: &[Property],
+        timezone: rrule::Tz,
+        replaced: &HashMap<String, Vec<DateTime<rrule::Tz>>>,
+    ) -> BoxResult<Option<Event>> {
+        let cancelled = find(props, "STATUS").is_some_and(|p| p.value.eq_ignore_ascii_case("CANCELLED"));
+        let free = find(props, "TRANSP").is_some_and(|p| p.value.eq_ignore_ascii_case("TRANSPARENT"));
+        if cancelled || free {
+            return Ok(None);
+        }
+
+        let start = match find(props, "DTSTART") {
+            Some(p) => match date_time(&p.value, p.param("TZID"), timezone)? {
+                Some(d) => d,
+                // All-day event.
+                None => return Ok(None),
+            },
+            None => bail!("DTSTART is missing"),
+        };
+        let duration = match (find(props, "DTEND"), find(props, "DURATION")) {
+            (Some(p), _) => match date_time(&p.value, p.param("TZID"), timezone)? {
+                Some(end) => end.signed_duration_since(start),
+                None => return Ok(None),
+            },
+            (None, Some(p)) => match duration(&p.value) {
+                Some(d) => d,
+                None => bail!("Invalid DURATION: {}", p.value),
+            },
+            (None, None) => Duration::zero(),
+        };
+        if duration <= Duration::zero() {
+            return Ok(None);
+        }
+
+        let rules: Vec<&Property> = props.iter().filter(|p| p.name == "RRULE").collect();
+        let rdates = date_times(props, "RDATE", timezone)?;
+        let recurrence = if rules.is_empty() && rdates.is_empty() {
+            None
+        } else {
+            let mut recurrence = Recurrence { rules: Vec::new(), rdates, exdates: Vec::new() };
+            for rule in rules {
+                let rule: RRule<Unvalidated> = until_utc(&rule.value, start.timezone()).parse()?;
+                rule.clone().validate(start)?;
+                recurrence.rules.push(rule);
+            }
+            if recurrence.rules.is_empty() {
+                // Event start is always an occurrence.
+                recurrence.rdates.push(start);
+            }
+            recurrence.exdates = date_times(props, "EXDATE", timezone)?;
+            let uid = find(props, "UID").map(|p| &p.value);
+            if find(props, "RECURRENCE-ID").is_none() {
+                recurrence.exdates.extend(uid.and_then(|u| replaced.get(u)).into_iter().flatten());
+            }
+            Some(recurrence)
+        };
+
+        Ok(Some(Event {
+            summary: find(props, "SUMMARY").map_or(String::new(), |p| p.value.clone()),
+            start,
+            duration,
+            recurrence,
+        }))
+    }
+
+    /// Occurrences going on between these times.
+    fn occurrences(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Meeting> {
+        let starts = match &self.recurrence {
+            Some(r) => r.starts(&self.summary, self.start, from - self.duration, to),
+            None => vec![self.start],
+        };
+
+        starts.into_iter()
+            .filter(|s| s.with_timezone(&Utc) <= to)
+            .map(|s| (s, s + self.duration))
+            .filter(|(_, end)| *end > from)
+            .map(|(start, end)| Meeting {
+                summary: self.summary.clone(),
+                start: start.with_timezone(&Utc),
+                end: end.with_timezone(&Utc),
+            })
+            .collect()
+    }
+}
+
+impl Recurrence {
+    /// Occurrence starts between these times. Rules are expanded from a start
+    /// moved just before `from` when they allow it, so long-running series
+    /// are not expanded from their first occurrence at each lookup.
+    fn starts(
+        &self,
+        summary: &str,
+        start: DateTime<rrule::Tz>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Vec<DateTime<rrule::Tz>> {
+        let tz = start.timezone();
+        let (from, to) = (from.with_timezone(&tz), to.with_timezone(&tz));
+        let mut starts: Vec<_> = self.rdates.iter().filter(|d| **d >= from && **d <= to).copied().collect();
+
+        for rule in &self.rules {
+            let rule_start = rebase(rule, start, from);
+            let mut set = match rule.clone().validate(rule_start) {
+                Ok(r) => RRuleSet::new(rule_start).rrule(r),
+                Err(e) => {
+                    warn!("Cannot expand recurrence of {}: {}", summary, e);
+                    continue;
+                },
+            };
+            for d in &self.exdates {
+                set = set.exdate(*d);
+            }
+
+            let result = set.after(from).before(to).all(MAX_OCCURRENCES);
+            if result.limited {
+                warn!("Too many occurrences of {}, some are skipped", summary);
+            }
+            starts.extend(result.dates);
+        }
+
+        starts.retain(|s| !self.exdates.contains(s));
+        starts.sort();
+        starts.dedup();
+        starts
+    }
+}
+
+/// Start of a rule moved a whole number of periods forward, a period or more
+/// before `from`. Rules counting occurrences, or with months or years, are
+/// not moved.
+fn rebase(rule: &RRule<Unvalidated>, start: DateTime<rrule::Tz>, from: DateTime<rrule::Tz>) -> DateTime<rrule::Tz> {
+    if rule.get_count().is_some() {
+        return start;
+    }
+    let unit = match rule.get_freq() {
+        Frequency::Weekly => Duration::weeks(1),
+        Frequency::Daily => Duration::days(1),
+        Frequency::Hourly => Duration::hours(1),
+        Frequency::Minutely => Duration::minutes(1),
+        Frequency::Secondly => Duration::seconds(1),
+        _ => return start,
+    };
+    let period = unit.num_seconds() * i64::from(rule.get_interval().max(1));
+
+    // Local times are kept, as rules repeat in local time.
+    let periods = (from - start).num_seconds() / period - 1;
+    if periods <= 0 {
+        return start;
+    }
+    let moved = start.naive_local() + Duration::seconds(period * periods);
+    start.timezone().from_local_datetime(&moved).earliest().unwrap_or(start)
+}
+
+impl Property {
+    /// Parse a content line, parameters values may be quoted.
+    fn parse(line: &str) -> Option<Property> {
+        let mut parts = Vec::new();
+        let mut quoted = false;
+        let mut begin = 0;
+        let mut value = None;
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' => quoted = !quoted,
+                ';' if !quoted => {
+                    parts.push(&line[begin..i]);
+                    begin = i + 1;
+                },
+                ':' if !quoted => {
+                    parts.push(&line[begin..i]);
+                    value = Some(&line[i + 1..]);
+                    break;
+                },
+                _ => (),
+            }
+        }
+
+        let mut parts = parts.into_iter();
+        let name = parts.next()?.trim().to_uppercase();
+        let params = parts.filter_map(|p| {
+            let (k, v) = p.split_once('=')?;
+            Some((k.to_uppercase(), v.trim_matches('"').to_string()))
+        }).collect();
+
+        Some(Property { name, params, value: value?.to_string() })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Join folded lines, continued on the next lines beginning with a space or a
+/// tab.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn find<'a>(props: &'a [Property], name: &str) -> Option<&'a Property> {
+    props.iter().find(|p| p.name == name)
+}
+
+/// Date-times of a property which may appear several times with lists of
+/// values, such as `EXDATE`. Dates are skipped.
+fn date_times(props: &[Property], name: &str, timezone: rrule::Tz) -> BoxResult<Vec<DateTime<rrule::Tz>>> {
+    let mut dates = Vec::new();
+    for p in props.iter().filter(|p| p.name == name) {
+        if p.param("VALUE").is_some_and(|v| v.eq_ignore_ascii_case("PERIOD")) {
+            warn!("{} periods are not supported", name);
+            continue;
+        }
+        for value in p.value.split(',') {
+            if let Some(d) = date_time(value, p.param("TZID"), timezone)? {
+                dates.push(d);
+            }
+        }
+    }
+    Ok(dates)
+}
+
+/// Parse a date-time in UTC (ending with `Z`), in its `TZID` timezone or in
+/// the default timezone. `None` for dates.
+fn date_time(value: &str, tzid: Option<&str>, timezone: rrule::Tz) -> BoxResult<Option<DateTime<rrule::Tz>>> {
+    let value = value.trim();
+    if value.len() == 8 {
+        return Ok(None);
+    }
+
+    let (value, tz) = match value.strip_suffix('Z') {
+        Some(v) => (v, rrule::Tz::UTC),
+        None => (value, tzid.map_or(timezone, |id| zone(id, timezone))),
+    };
+    let naive = match NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        Ok(n) => n,
+        Err(e) => bail!("Invalid date-time {}: {}", value, e),
+    };
+
+    match tz.from_local_datetime(&naive).earliest() {
+        Some(d) => Ok(Some(d)),
+        None => bail!("{} does not exist in {}", naive, tz.name()),
+    }
+}
+
+/// Timezone from its IANA name, or the default timezone.
+fn zone(tzid: &str, timezone: rrule::Tz) -> rrule::Tz {
+    match Tz::from_str(tzid.trim_start_matches('/')) {
+        Ok(tz) => rrule::Tz::Tz(tz),
+        Err(_) => {
+            warn!("Unknown timezone {}, using {}", tzid, timezone.name());
+            timezone
+        },
+    }
+}
+
+/// Parse a duration, such as `PT1H30M` or `P1D`.
+fn duration(value: &str) -> Option<Duration> {
+    let (negative, value) = match value.trim().strip_prefix('-') {
+        Some(v) => (true, v),
+        None => (false, value.trim().trim_start_matches('+')),
+    };
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in value.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => (),
+            'W' | 'D' | 'H' | 'M' | 'S' => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                total += match c {
+                    'W' => Duration::weeks(n),
+                    'D' => Duration::days(n),
+                    'H' => Duration::hours(n),
+                    'M' => Duration::minutes(n),
+                    _ => Duration::seconds(n),
+                };
+            },
+            _ => return None,
+        }
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+    Some(if negative { -total } else { total })
+}
+
+/// Write the end (`UNTIL`) of a recurrence rule in UTC, as required when the
+/// event start has a timezone. Calendars often write it in local time.
+fn until_utc(rule: &str, tz: rrule::Tz) -> String {
+    rule.split(';').map(|part| match part.split_once('=') {
+        Some((k, v)) if k.eq_ignore_ascii_case("UNTIL") && !v.ends_with('Z') => {
+            let naive = match NaiveDate::parse_from_str(v, "%Y%m%d") {
+                Ok(d) => d.and_hms_opt(23, 59, 59),
+                Err(_) => NaiveDateTime::parse_from_str(v, "%Y%m%dT%H%M%S").ok(),
+            };
+            match naive.and_then(|n| tz.from_local_datetime(&n).earliest()) {
+                Some(d) => format!("UNTIL={}", d.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")),
+                None => part.to_string(),
+            }
+        },
+        _ => part.to_string(),
+    }).collect::<Vec<_>>().join(";")
+}
@@ -1,61 +1,447 @@
+use std::cmp::Ordering;
 use std::fs::File;
 use std::error::Error;
 use std::fmt;
 use std::fs::create_dir_all;
 use std::io::prelude::*;
-use std::net::IpAddr;
 use std::path::PathBuf;
 
+use super::calendar::Calendar;
+use super::detector::gps::GPSD_ADDRESS;
+use super::detector::{DETECTORS, ON_VPN, PROC_ROOT, PUBLIC_IP, SYS_ROOT, UNKNOWN_LOCATION, VPN};
+use super::ip_lookup::{check_source, serialize_services, IpService};
+use super::geoip::GeoIp;
 use super::location::Location;
+use super::network::Network;
+use super::ooo::OutOfOffice;
+use super::rules::Rule;
+use super::schedule::Schedule;
+use super::script::Script;
+use super::stability::Stability;
+use super::template::Template;
 
+use chrono::Duration;
+use chrono_tz::Tz;
 use directories::ProjectDirs;
+use glob::Pattern;
+use serde::{Deserialize, Deserializer};
 
 type BoxResult<T> = Result<T,Box<dyn Error>>;
 
 /// Slack Status, as sent to the API.
-#[derive(Serialize, Deserialize, Clone)]
+///
+/// Computed statuses may expire at a given time (`expiration` timestamp)
+/// instead of after some hours.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
 pub struct StatusConfig {
     pub text: String,
     pub emoji: String,
     pub expire_after_hours: Option<i64>,
+    #[serde(skip)]
+    pub expiration: Option<i64>,
+}
+
+/// Status to set when moving from a location to another:
+///
+/// * from: Location left, any if not set.
+/// * to: Location entered, any if not set.
+///
+/// Locations are named as in `Location.label`, `unknown` when no location is
+/// found and `vpn` while a VPN is up.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Transition {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub text: String,
+    pub emoji: String,
+    pub expire_after_hours: Option<i64>,
 }
 
 /// Config, as read/write in configuration TOML file.
 ///
 /// * token: Slack token, must have r/w right on user profile.
-/// * ip_request_address: URL to request public IP address.
-/// * locations: List of Location to set profile.
-/// * ignore_ips: List of public IPs to ignore when setting status, such as
-///               VPNs output addresses. In this case the cached status is
-///               used instead.
+/// * ip_request_address: Services to request public IP address, either one or
+///   a list, see `IpService`. Services are asked in order until one answers.
+/// * ip_request_timeout: Timeout of each public IP request, in seconds (at least 1).
+/// * ip_quorum: Number of services which must agree on the public IP (1 by
+///   default).
+/// * ip_request_source: Network interface name (such as `wlp2s0`) or local
+///   address to send public IP requests from, to find the real public IP
+///   while a VPN is up.
+/// * detectors: Location detectors to ask, in order, the first confident one
+///   wins (`public_ip` by default).
+/// * locations: List of Location to set profile, see `Location` for the
+///   precedence rules when several locations match.
+/// * proc_root: Where the proc filesystem is mounted (`/proc` by default),
+///   read by the `ssid`, `gateway_mac` and `vpn` detectors.
+/// * sys_root: Where the sys filesystem is mounted (`/sys` by default), read by
+///   the `vpn` detector.
+/// * gpsd_address: Address of gpsd, read by the `gps` detector
+///   (`127.0.0.1:2947` by default).
+/// * vpn_interfaces: Names of VPN network interfaces, or glob patterns such as
+///   `tun*` and `wg*`. Their addresses are ignored by the `local_network`
+///   detector, and the `vpn` detector checks whether one of them is up.
+/// * vpn_status: Status to use while a VPN is up, the cached status is kept if
+///   not set.
+/// * ignore_ips: List of public IPs or networks to ignore when setting status,
+///   such as VPNs output addresses. In this case the cached status is
+///   used instead.
+/// * geoip_database: Path of an offline GeoIP database (`.mmdb` file, such as
+///   GeoLite2 City), to describe public IPs matching no location.
+/// * geoip_status: Status to use when the public IP matches no location but is
+///   found in the GeoIP database, see `Variables` for the variables
+///   ("working from {city}" with the country flag by default).
+/// * transitions: Statuses to set when moving from a location to another, the
+///   first matching one wins, see `Transition`. They are kept until they
+///   expire or location changes.
+/// * rules: Ordered status rules with conditions, the first matching one wins
+///   over every status but out-of-office ones, see `Rule`.
+/// * grace_period: Minutes after a known location was last seen during which
+///   location detection failures and unknown locations keep its status.
+/// * ooo: Out-of-office date ranges, automatic updates are suspended while one
+///   is active, see `OutOfOffice`.
+/// * calendar: Calendar to set status during meetings, it wins over schedule
+///   and location statuses, see `Calendar`.
+/// * script: Script deciding the status from the detected context and the
+///   built-in status, see `Script`.
+/// * schedule: Statuses depending on time, they win over location ones, see
+///   `Schedule`.
+/// * stability: How long a new location must be detected before switching
+///   status to it, see `Stability`.
 /// * defaults: Status to use when you have no status associated to location.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     pub token: String,
-    pub ip_request_address: Option<String>,
-    pub ignore_ips: Vec<IpAddr>,
+    #[serde(default, deserialize_with = "one_or_many", serialize_with = "serialize_services")]
+    pub ip_request_address: Vec<IpService>,
+    pub ip_request_timeout: Option<u64>,
+    pub ip_quorum: Option<usize>,
+    pub ip_request_source: Option<String>,
+    #[serde(default = "default_detectors")]
+    pub detectors: Vec<String>,
+    pub proc_root: Option<String>,
+    pub sys_root: Option<String>,
+    pub gpsd_address: Option<String>,
+    #[serde(default)]
+    pub vpn_interfaces: Vec<String>,
+    pub geoip_database: Option<String>,
+    pub grace_period: Option<i64>,
+    pub ignore_ips: Vec<Network>,
+    // Tables come last in TOML.
+    pub vpn_status: Option<StatusConfig>,
     pub locations: Vec<Location>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transitions: Vec<Transition>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    pub geoip_status: Option<StatusConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ooo: Vec<OutOfOffice>,
+    pub calendar: Option<Calendar>,
+    pub script: Option<Script>,
+    pub schedule: Option<Schedule>,
+    pub stability: Option<Stability>,
     pub defaults: Option<StatusConfig>,
 }
 
+/// Read either a single value or a list of values.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where D: Deserializer<'de>, T: Deserialize<'de>
+{
+    // Lists come first, a list could be read as a struct.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        Many(Vec<T>),
+        One(T),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(v) => Ok(vec![v]),
+        OneOrMany::Many(v) => Ok(v),
+    }
+}
+
+fn default_detectors() -> Vec<String> {
+    vec![PUBLIC_IP.to_string()]
+}
+
+impl StatusConfig {
+    /// Expiration timestamp of the status if set at `now`, if it expires.
+    pub fn expiration_at(&self, now: i64) -> Option<i64> {
+        match (self.expiration, self.expire_after_hours) {
+            (Some(e), _) => Some(e),
+            (None, Some(h)) => Some(now + Duration::hours(h).num_seconds()),
+            (None, None) => None,
+        }
+    }
+}
+
 impl fmt::Display for StatusConfig {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} {}", self.emoji, self.text)
     }
 }
 
+impl Transition {
+    /// Whether the transition applies when moving between these locations.
+    pub fn matches(&self, from: &str, to: &str) -> bool {
+        self.from.as_ref().is_none_or(|f| f == from) && self.to.as_ref().is_none_or(|t| t == to)
+    }
+
+    /// Status to set on transition.
+    pub fn status(&self) -> StatusConfig {
+        StatusConfig {
+            text: self.text.clone(),
+            emoji: self.emoji.clone(),
+            expire_after_hours: self.expire_after_hours,
+            expiration: None,
+        }
+    }
+}
+
 impl Config {
     /// Create minimal config with token.
     pub fn with(token: String) -> Config {
         Config {
-            token: token,
-            ip_request_address: None,
-            ignore_ips: Vec::<IpAddr>::new(),
+            token,
+            ip_request_address: Vec::<IpService>::new(),
+            ip_request_timeout: None,
+            ip_quorum: None,
+            ip_request_source: None,
+            detectors: default_detectors(),
+            proc_root: None,
+            sys_root: None,
+            gpsd_address: None,
+            vpn_interfaces: Vec::<String>::new(),
+            vpn_status: None,
+            ignore_ips: Vec::<Network>::new(),
             locations: Vec::<Location>::new(),
+            geoip_database: None,
+            geoip_status: None,
+            transitions: Vec::<Transition>::new(),
+            rules: Vec::<Rule>::new(),
+            grace_period: None,
+            ooo: Vec::<OutOfOffice>::new(),
+            calendar: None,
+            script: None,
+            schedule: None,
+            stability: None,
             defaults: None,
         }
     }
 
+    /// Where the proc filesystem is mounted.
+    pub fn proc_root(&self) -> PathBuf {
+        PathBuf::from(self.proc_root.as_deref().unwrap_or(PROC_ROOT))
+    }
+
+    /// Where the sys filesystem is mounted.
+    pub fn sys_root(&self) -> PathBuf {
+        PathBuf::from(self.sys_root.as_deref().unwrap_or(SYS_ROOT))
+    }
+
+    /// Address of gpsd.
+    pub fn gpsd_address(&self) -> String {
+        self.gpsd_address.clone().unwrap_or_else(|| GPSD_ADDRESS.to_string())
+    }
+
+    /// Status to use for places found in the GeoIP database.
+    pub fn geoip_status(&self) -> StatusConfig {
+        self.geoip_status.clone().unwrap_or(StatusConfig {
+            text: "working from {city}".to_string(),
+            emoji: "{flag}".to_string(),
+            expire_after_hours: None,
+            expiration: None,
+        })
+    }
+
+    /// Whether `now` is within the grace period after a known location was
+    /// `last_seen` (timestamps).
+    pub fn in_grace_period(&self, last_seen: Option<i64>, now: i64) -> bool {
+        match (self.grace_period, last_seen) {
+            (Some(minutes), Some(seen)) => now - seen <= minutes * 60,
+            _ => false,
+        }
+    }
+
+    /// Timezone of the schedule and out-of-office ranges, system timezone if
+    /// not set.
+    pub fn timezone(&self) -> Option<Tz> {
+        self.schedule.as_ref().and_then(|s| s.timezone().ok().flatten())
+    }
+
+    /// Whether the name is a location name, as used in transitions and
+    /// schedule rules.
+    fn is_location_name(&self, name: &str) -> bool {
+        name == UNKNOWN_LOCATION || name == ON_VPN || self.locations.iter().any(|l| l.label() == name)
+    }
+
+    /// Describe invalid status templates, such as unknown variables.
+    pub fn check_templates(&self) -> Vec<String> {
+        // Where statuses are set, with their text and emoji.
+        let mut statuses = Vec::<(String, &str, &str)>::new();
+        for l in &self.locations {
+            statuses.push((format!("location \"{}\"", l.label()), &l.text, &l.emoji));
+            if let Some(s) = &l.on_leave {
+                statuses.push((format!("location \"{}\" on_leave", l.label()), &s.text, &s.emoji));
+            }
+        }
+        for (name, status) in [("vpn_status", &self.vpn_status), ("geoip_status", &self.geoip_status),
+                               ("defaults", &self.defaults)] {
+            if let Some(s) = status {
+                statuses.push((name.to_string(), &s.text, &s.emoji));
+            }
+        }
+        for t in &self.transitions {
+            statuses.push((
+                format!("transition from {} to {}", t.from.as_deref().unwrap_or("any"), t.to.as_deref().unwrap_or("any")),
+                &t.text, &t.emoji));
+        }
+        for r in &self.rules {
+            statuses.push((format!("rule \"{}\"", r.label()),
+                r.text.as_deref().unwrap_or_default(), r.emoji.as_deref().unwrap_or_default()));
+        }
+        for o in &self.ooo {
+            statuses.push((format!("out-of-office range from {} to {}", o.from, o.to), &o.text, &o.emoji));
+        }
+        if let Some(c) = &self.calendar {
+            statuses.push(("calendar".to_string(), &c.text, &c.emoji));
+        }
+        for r in self.schedule.iter().flat_map(|s| &s.rules) {
+            statuses.push((format!("schedule rule \"{}\"", r.text), &r.text, &r.emoji));
+        }
+
+        let mut errors = Vec::<String>::new();
+        for (name, text, emoji) in statuses {
+            for (field, template) in [("text", text), ("emoji", emoji)] {
+                if let Err(e) = Template::parse(template) {
+                    errors.push(format!("{} {} template: {}", name, field, e));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Check configuration consistency, every problem found is reported.
+    pub fn check(&self) -> BoxResult<()> {
+        let mut errors = self.check_templates();
+
+        if self.ip_quorum == Some(0) || self.ip_quorum.unwrap_or(1) > self.ip_request_address.len().max(1) {
+            errors.push(format!(
+                "ip_quorum must be between 1 and the number of services in ip_request_address ({})",
+                self.ip_request_address.len().max(1)));
+        }
+
+        if self.ip_request_timeout == Some(0) {
+            errors.push("ip_request_timeout must be at least 1 second".to_string());
+        }
+
+        for service in &self.ip_request_address {
+            if let Err(e) = service.check() {
+                errors.push(e);
+            }
+        }
+
+        if let Some(Err(e)) = self.ip_request_source.as_ref().map(|s| check_source(s)) {
+            errors.push(format!("ip_request_source: {}", e));
+        }
+
+        for name in &self.detectors {
+            if !DETECTORS.contains(&name.as_str()) {
+                errors.push(format!(
+                    "unknown location detector \"{}\", expected one of: {}",
+                    name, DETECTORS.join(", ")));
+            }
+        }
+
+        for pattern in &self.vpn_interfaces {
+            if let Err(e) = Pattern::new(pattern) {
+                errors.push(format!("invalid vpn_interfaces pattern \"{}\": {}", pattern, e));
+            }
+        }
+        if self.vpn_status.is_some() && !self.detectors.iter().any(|d| d == VPN) {
+            errors.push("vpn_status is set but the vpn detector is not enabled".to_string());
+        }
+        if !self.vpn_interfaces.is_empty() && !self.detectors.iter().any(|d| d == VPN) {
+            warn!("vpn_interfaces is set but the vpn detector is not enabled, VPNs are not detected.");
+        }
+
+        if let Some(path) = &self.geoip_database {
+            if let Err(e) = GeoIp::open(path) {
+                errors.push(e.to_string());
+            }
+        }
+
+        for t in &self.transitions {
+            for name in t.from.iter().chain(t.to.iter()) {
+                if !self.is_location_name(name) {
+                    errors.push(format!(
+                        "transition from {} to {} refers to unknown location \"{}\"",
+                        t.from.as_deref().unwrap_or("any"), t.to.as_deref().unwrap_or("any"), name));
+                }
+            }
+        }
+
+        for r in &self.rules {
+            if let Err(e) = r.check() {
+                errors.push(e);
+            }
+        }
+
+        for o in &self.ooo {
+            if o.from > o.to {
+                errors.push(format!("out-of-office range from {} to {} ends before it starts", o.from, o.to));
+            }
+        }
+
+        if let Some(Err(e)) = self.calendar.as_ref().map(|c| c.check(self.timezone())) {
+            errors.push(format!("calendar: {}", e));
+        }
+        if let Some(Err(e)) = self.script.as_ref().map(|s| s.check()) {
+            errors.push(e);
+        }
+
+        if let Some(schedule) = &self.schedule {
+            errors.extend(schedule.check());
+            for rule in &schedule.rules {
+                if let Some(name) = rule.location.as_ref().filter(|n| !self.is_location_name(n)) {
+                    errors.push(format!("schedule rule \"{}\" refers to unknown location \"{}\"", rule.text, name));
+                }
+            }
+        }
+
+        // Locations sharing a key with the same priority cannot be told apart.
+        for (i, a) in self.locations.iter().enumerate() {
+            if a.has_no_key() {
+                errors.push(format!(
+                    "location \"{}\" has no ip, ip6, ssid, gateway_mac, local_network nor geofence, it never matches",
+                    a.label()));
+            }
+            if let Some(Err(e)) = a.geofence.map(|g| g.check()) {
+                errors.push(format!("location \"{}\" geofence: {}", a.label(), e));
+            }
+            if let Some(n) = a.ip6.filter(|n| n.addr().is_ipv4()) {
+                errors.push(format!("location \"{}\" ip6 {} is not an IPv6 network", a.label(), n));
+            }
+
+            for b in self.locations.iter().skip(i + 1) {
+                let common = a.common_keys(b);
+                if !common.is_empty() && a.precedence(b) == Ordering::Equal {
+                    errors.push(format!(
+                        "locations \"{}\" and \"{}\" both match {} with the same priority",
+                        a.label(), b.label(), common.join(", ")));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            bail!("Configuration error:\n  - {}", errors.join("\n  - "))
+        }
+    }
+
     /// Get the configuration file path either provided by the user or look at
     /// default location:
     ///
@@ -94,11 +480,17 @@ impl Config {
                     f.read_to_string(&mut contents)
                         .expect("something went wrong reading the file");
 
-                    let config = match toml::from_str(contents.as_str()) {
+                    let config: Config = match toml::from_str(contents.as_str()) {
                         Ok(c) => c,
                         Err(e) => bail!("Deserialization error: {}", e),
                     };
 
+                    // Templates are only rendered later, check them now.
+                    let errors = config.check_templates();
+                    if !errors.is_empty() {
+                        bail!("Invalid status template:\n{}", errors.join("\n"));
+                    }
+
                     Ok(Some(config))
                 }
                 Err(e) => {
@@ -117,7 +509,11 @@ impl Config {
         if let Some(config_file_path) = Config::get_file_path(path) {
             match File::create(config_file_path) {
                 Ok(mut f) => {
-                    let config = match toml::to_string_pretty(self) {
+                    // Going through a TOML value puts tables after plain values,
+                    // as required by TOML.
+                    let config = match toml::Value::try_from(self)
+                        .and_then(|v| toml::to_string_pretty(&v))
+                    {
                         Ok(c) => c,
                         Err(e) => bail!("Serialization error: {}", e),
                     };
@@ -0,0 +1,127 @@
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+use super::{Detection, LocationDetector, GATEWAY_MAC};
+use crate::config::Config;
+use crate::location;
+use crate::BoxResult;
+
+/// Match the MAC address of the default gateway against locations
+/// `gateway_mac`, no request is sent to the Internet.
+///
+/// The default route is read from `/proc/net/route` and the gateway MAC address
+/// from the neighbour table, in `/proc/net/arp` (Linux only).
+pub struct GatewayMacDetector {
+    proc_root: PathBuf,
+}
+
+impl GatewayMacDetector {
+    pub fn new(proc_root: PathBuf) -> GatewayMacDetector {
+        GatewayMacDetector { proc_root }
+    }
+
+    /// Get the MAC address of the default gateway, if there is a default route
+    /// and the gateway has been resolved.
+    pub fn gateway_mac(&self) -> BoxResult<Option<String>> {
+        let route = match fs::read_to_string(self.proc_root.join("net/route")) {
+            Ok(r) => r,
+            Err(e) => bail!("Cannot read routing table: {}", e),
+        };
+
+        let (interface, gateway) = match default_gateway(&route) {
+            Some(g) => g,
+            None => {
+                debug!("No default route");
+                return Ok(None);
+            },
+        };
+        debug!("Default gateway: {} on {}", gateway, interface);
+
+        let arp = match fs::read_to_string(self.proc_root.join("net/arp")) {
+            Ok(a) => a,
+            Err(e) => bail!("Cannot read neighbour table: {}", e),
+        };
+
+        Ok(neighbour_mac(&arp, &gateway, &interface))
+    }
+}
+
+impl LocationDetector for GatewayMacDetector {
+    fn name(&self) -> &str {
+        GATEWAY_MAC
+    }
+
+    fn detect(&self, config: &Config, detection: &mut Detection) -> BoxResult<bool> {
+        let mac = match self.gateway_mac()? {
+            Some(m) => m,
+            None => return Ok(false),
+        };
+        detection.gateway_mac = Some(mac.clone());
+
+        match location::best_match(&config.locations, |l| l.matches_gateway_mac(&mac)) {
+            Some(l) => {
+                debug!("{} => {}", mac, l.label());
+                detection.location = Some(l.clone());
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+/// Get the interface and gateway of the default route with the lowest metric
+/// from `/proc/net/route` content.
+///
+/// Addresses are the in-memory (network byte order) words printed as
+/// hexadecimal host integers, so their native bytes are the address bytes.
+pub fn default_gateway(route: &str) -> Option<(String, Ipv4Addr)> {
+    const RTF_UP: u32 = 0x1;
+    const RTF_GATEWAY: u32 = 0x2;
+
+    route.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 8 {
+                return None;
+            }
+
+            let destination = u32::from_str_radix(fields[1], 16).ok()?;
+            let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+            let flags = u32::from_str_radix(fields[3], 16).ok()?;
+            let metric: u32 = fields[6].parse().ok()?;
+            let mask = u32::from_str_radix(fields[7], 16).ok()?;
+
+            if destination != 0 || mask != 0 || flags & (RTF_UP | RTF_GATEWAY) != RTF_UP | RTF_GATEWAY {
+                return None;
+            }
+
+            Some((metric, fields[0].to_string(), Ipv4Addr::from(gateway.to_ne_bytes())))
+        })
+        .min_by_key(|(metric, _, _)| *metric)
+        .map(|(_, interface, gateway)| (interface, gateway))
+}
+
+/// Get the MAC address of a neighbour from `/proc/net/arp` content, ignoring
+/// incomplete entries.
+pub fn neighbour_mac(arp: &str, ip: &Ipv4Addr, interface: &str) -> Option<String> {
+    const ATF_COM: u32 = 0x2;
+
+    arp.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 || fields[5] != interface || fields[0].parse::<Ipv4Addr>().ok()? != *ip {
+                return None;
+            }
+
+            let flags = u32::from_str_radix(fields[2].trim_start_matches("0x"), 16).ok()?;
+            if flags & ATF_COM == 0 {
+                return None;
+            }
+
+            Some(location::normalize_mac(fields[3]))
+        })
+        .next()
+}
@@ -0,0 +1,126 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use super::{Detection, LocationDetector, GPS};
+use crate::config::Config;
+use crate::geofence::Position;
+use crate::ip_lookup::resolve;
+use crate::location::Location;
+use crate::BoxResult;
+
+/// Where gpsd listens by default.
+pub const GPSD_ADDRESS: &str = "127.0.0.1:2947";
+
+/// How long to wait for a fix.
+const FIX_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Match the position read from gpsd against locations `geofence`, no request
+/// is sent to the Internet.
+///
+/// The position is read from gpsd JSON protocol: after watching reports, the
+/// first TPV report with a 2D or 3D fix is used.
+pub struct GpsDetector {
+    address: String,
+}
+
+impl GpsDetector {
+    pub fn new(address: String) -> GpsDetector {
+        GpsDetector { address }
+    }
+
+    /// Get current position, if gpsd has a fix.
+    pub fn position(&self) -> BoxResult<Option<Position>> {
+        let server = resolve(&self.address, 2947, None)?;
+        debug!("Reading position from gpsd at {}...", server);
+
+        let mut stream = match TcpStream::connect_timeout(&server, FIX_TIMEOUT) {
+            Ok(s) => s,
+            Err(e) => bail!("Cannot connect to gpsd at {}: {}", server, e),
+        };
+        stream.set_read_timeout(Some(FIX_TIMEOUT))?;
+        stream.write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")?;
+
+        let deadline = Instant::now() + FIX_TIMEOUT;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        while Instant::now() < deadline {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(0) => bail!("gpsd closed the connection"),
+                Ok(_) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut => break,
+                Err(e) => bail!("Cannot read from gpsd: {}", e),
+            }
+
+            if let Some(p) = parse_report(&line)? {
+                return Ok(Some(p));
+            }
+        }
+
+        debug!("No GPS fix");
+        Ok(None)
+    }
+}
+
+impl LocationDetector for GpsDetector {
+    fn name(&self) -> &str {
+        GPS
+    }
+
+    fn detect(&self, config: &Config, detection: &mut Detection) -> BoxResult<bool> {
+        let position = match self.position()? {
+            Some(p) => p,
+            None => return Ok(false),
+        };
+        detection.position = Some(position);
+
+        match nearest_geofence(&config.locations, &position) {
+            Some(l) => {
+                debug!("{} => {}", position, l.label());
+                detection.location = Some(l.clone());
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+/// Get the position from a gpsd report, if it is a TPV report with a fix.
+pub fn parse_report(report: &str) -> BoxResult<Option<Position>> {
+    let value: Value = match serde_json::from_str(report) {
+        Ok(v) => v,
+        Err(e) => bail!("Invalid gpsd report: {}", e),
+    };
+
+    if value["class"] != "TPV" || value["mode"].as_u64().unwrap_or(0) < 2 {
+        return Ok(None);
+    }
+
+    match (value["lat"].as_f64(), value["lon"].as_f64()) {
+        (Some(latitude), Some(longitude)) => Ok(Some(Position { latitude, longitude })),
+        _ => Ok(None),
+    }
+}
+
+/// Find the location whose geofence contains the position, the one with the
+/// highest priority wins, then the one with the nearest center.
+pub fn nearest_geofence<'a>(locations: &'a [Location], position: &Position) -> Option<&'a Location> {
+    let mut candidates: Vec<(&Location, f64)> = locations.iter()
+        .filter_map(|l| {
+            let g = l.geofence.as_ref()?;
+            Some((l, g.center().distance(position))).filter(|_| g.contains(position))
+        })
+        .collect();
+
+    // Stable sort, configuration order is kept for ties.
+    candidates.sort_by(|(a, da), (b, db)| {
+        b.priority.unwrap_or(0).cmp(&a.priority.unwrap_or(0))
+            .then(da.total_cmp(db))
+    });
+
+    candidates.first().map(|(l, _)| *l)
+}
@@ -0,0 +1,86 @@
+use std::net::IpAddr;
+
+use if_addrs::{get_if_addrs, IfAddr};
+
+use super::{is_vpn_interface, Detection, LocationDetector, LOCAL_NETWORK};
+use crate::config::Config;
+use crate::location;
+use crate::network::Network;
+use crate::BoxResult;
+
+/// Address of a local network interface.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalAddress {
+    pub interface: String,
+    pub ip: IpAddr,
+    pub network: Network,
+}
+
+/// Match local interfaces addresses against locations `local_network`, no
+/// request is sent to the Internet.
+///
+/// Loopback, link-local and VPN interfaces addresses are ignored.
+pub struct LocalNetworkDetector {
+    vpn_interfaces: Vec<String>,
+}
+
+impl LocalNetworkDetector {
+    pub fn new(vpn_interfaces: Vec<String>) -> LocalNetworkDetector {
+        LocalNetworkDetector { vpn_interfaces }
+    }
+
+    /// List addresses of local interfaces.
+    pub fn local_addresses(&self) -> BoxResult<Vec<LocalAddress>> {
+        let interfaces = match get_if_addrs() {
+            Ok(i) => i,
+            Err(e) => bail!("Cannot list network interfaces: {}", e),
+        };
+
+        let mut addresses = Vec::<LocalAddress>::new();
+        for interface in interfaces {
+            if interface.is_loopback() || interface.is_link_local()
+                || is_vpn_interface(&interface.name, &self.vpn_interfaces)
+            {
+                continue;
+            }
+
+            let prefix_len = match &interface.addr {
+                IfAddr::V4(a) => a.prefixlen,
+                IfAddr::V6(a) => a.prefixlen,
+            };
+            addresses.push(LocalAddress {
+                ip: interface.ip(),
+                network: Network::new(interface.ip(), prefix_len)?,
+                interface: interface.name,
+            });
+        }
+
+        Ok(addresses)
+    }
+}
+
+impl LocationDetector for LocalNetworkDetector {
+    fn name(&self) -> &str {
+        LOCAL_NETWORK
+    }
+
+    fn detect(&self, config: &Config, detection: &mut Detection) -> BoxResult<bool> {
+        let addresses = self.local_addresses()?;
+        debug!("Local addresses: {:?}", addresses);
+        detection.local_ips = addresses.iter().map(|a| a.ip).collect();
+
+        match find_location(&config.locations, &detection.local_ips) {
+            Some(l) => {
+                debug!("{:?} => {}", detection.local_ips, l.label());
+                detection.location = Some(l.clone());
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+/// Find the location whose local network contains one of the addresses.
+pub fn find_location<'a>(locations: &'a [location::Location], ips: &[IpAddr]) -> Option<&'a location::Location> {
+    location::best_match(locations, |l| ips.iter().any(|ip| l.matches_local_ip(ip)))
+}
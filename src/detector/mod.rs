@@ -0,0 +1,125 @@
+//! Location detectors.
+//!
+//! Each detector looks at a different aspect of current environment (public
+//! IP, Wi-Fi network...) to find which configured location we are at. They are
+//! asked in turn, in the order set by `Config.detectors`, and the first
+//! confident one wins.
+
+use std::net::IpAddr;
+
+use glob::Pattern;
+
+use super::config::Config;
+use super::geofence::Position;
+use super::geoip::Place;
+use super::location::Location;
+use super::BoxResult;
+
+pub mod gateway_mac;
+pub mod gps;
+pub mod local_network;
+pub mod public_ip;
+pub mod ssid;
+pub mod vpn;
+
+pub use gateway_mac::GatewayMacDetector;
+pub use gps::GpsDetector;
+pub use local_network::LocalNetworkDetector;
+pub use public_ip::PublicIpDetector;
+pub use ssid::SsidDetector;
+pub use vpn::VpnDetector;
+
+/// Detector asking the public IP to `ip_request_address`.
+pub const PUBLIC_IP: &str = "public_ip";
+
+/// Detector reading the SSID of current Wi-Fi network.
+pub const SSID: &str = "ssid";
+
+/// Detector reading the MAC address of the default gateway.
+pub const GATEWAY_MAC: &str = "gateway_mac";
+
+/// Detector reading local interfaces addresses.
+pub const LOCAL_NETWORK: &str = "local_network";
+
+/// Detector reading current position from gpsd.
+pub const GPS: &str = "gps";
+
+/// Detector looking for VPN interfaces.
+pub const VPN: &str = "vpn";
+
+/// Names of every available detectors, as used in configuration file.
+pub const DETECTORS: &[&str] = &[PUBLIC_IP, SSID, GATEWAY_MAC, LOCAL_NETWORK, GPS, VPN];
+
+/// Name of the location while a VPN is up.
+pub const ON_VPN: &str = "vpn";
+
+/// Name of the location when none is found.
+pub const UNKNOWN_LOCATION: &str = "unknown";
+
+/// Where the proc filesystem is mounted by default.
+pub const PROC_ROOT: &str = "/proc";
+
+/// Where the sys filesystem is mounted by default.
+pub const SYS_ROOT: &str = "/sys";
+
+/// Facts gathered while detecting current location.
+#[derive(Clone, Debug, Default)]
+pub struct Detection {
+    /// Location found, if any.
+    pub location: Option<Location>,
+    /// Name of the detector which found the location.
+    pub detector: Option<String>,
+    /// Current network is set to be ignored, the cached status must be used.
+    pub ignored: bool,
+    /// VPN interface up, if any.
+    pub vpn: Option<String>,
+    /// Current public IPv4 and IPv6 addresses, if they have been requested.
+    pub ips: Vec<IpAddr>,
+    /// Current Wi-Fi network SSID, if it has been read.
+    pub ssid: Option<String>,
+    /// Default gateway MAC address, if it has been read.
+    pub gateway_mac: Option<String>,
+    /// Local interfaces addresses, if they have been read.
+    pub local_ips: Vec<IpAddr>,
+    /// Where current public IP is, if it matches no location and a GeoIP
+    /// database is configured.
+    pub place: Option<Place>,
+    /// Current GPS position, if there is a fix.
+    pub position: Option<Position>,
+}
+
+impl Detection {
+    /// Name of the location found, `vpn` while a VPN is up, `unknown`
+    /// otherwise.
+    pub fn location_name(&self) -> String {
+        match (&self.location, &self.vpn) {
+            (Some(l), _) => l.label(),
+            (None, Some(_)) => ON_VPN.to_string(),
+            (None, None) => UNKNOWN_LOCATION.to_string(),
+        }
+    }
+}
+
+/// Find current location from some aspect of the environment.
+pub trait LocationDetector {
+    /// Name of the detector, as used in configuration file.
+    fn name(&self) -> &str;
+
+    /// Look for the configured location matching current environment, and
+    /// record what has been observed in `detection`.
+    ///
+    /// Returns `true` when the detector is confident, either because it found
+    /// a location or because current network must be ignored. Otherwise the
+    /// next detector is asked.
+    fn detect(&self, config: &Config, detection: &mut Detection) -> BoxResult<bool>;
+}
+
+/// Whether the network interface is a VPN, matching one of the
+/// `vpn_interfaces` glob patterns (such as `tun*`). Invalid patterns are
+/// compared as plain names.
+pub fn is_vpn_interface(name: &str, vpn_interfaces: &[String]) -> bool {
+    vpn_interfaces.iter().any(|i| match Pattern::new(i) {
+        Ok(p) => p.matches(name),
+        Err(_) => i == name,
+    })
+}
@@ -0,0 +1,80 @@
+use std::net::IpAddr;
+
+use super::{Detection, LocationDetector, PUBLIC_IP};
+use crate::config::Config;
+use crate::geoip::{GeoIp, Place};
+use crate::location;
+use crate::{BoxResult, SlackStatus};
+
+/// Match current public IPv4 and IPv6 addresses against locations networks.
+pub struct PublicIpDetector<'a> {
+    client: &'a SlackStatus<'a>,
+}
+
+impl<'a> PublicIpDetector<'a> {
+    pub fn new(client: &'a SlackStatus<'a>) -> PublicIpDetector<'a> {
+        PublicIpDetector { client }
+    }
+}
+
+impl<'a> LocationDetector for PublicIpDetector<'a> {
+    fn name(&self) -> &str {
+        PUBLIC_IP
+    }
+
+    fn detect(&self, config: &Config, detection: &mut Detection) -> BoxResult<bool> {
+        let ips = self.client.get_public_ips()?;
+        Ok(match_ips(config, ips, detection))
+    }
+}
+
+/// Match public addresses against locations networks and ignored networks,
+/// as the detector does. Returns `true` when a location is found or the
+/// addresses are ignored.
+pub fn match_ips(config: &Config, ips: Vec<IpAddr>, detection: &mut Detection) -> bool {
+    detection.ips = ips.clone();
+
+    if let Some(ip) = ips.iter().find(|ip| config.ignore_ips.iter().any(|i| i.contains(ip))) {
+        debug!("{} is ignored", ip);
+        detection.ignored = true;
+        return true;
+    }
+
+    match location::best_match(&config.locations, |l| ips.iter().any(|ip| l.matches_ip(ip))) {
+        Some(l) => {
+            debug!("{:?} => {}", ips, l.label());
+            detection.location = Some(l.clone());
+            true
+        },
+        None => {
+            if let Some(path) = &config.geoip_database {
+                detection.place = find_place(path, &ips);
+            }
+            false
+        },
+    }
+}
+
+/// Find where one of the addresses is in the GeoIP database, errors are only
+/// logged as the database is just used to describe unknown locations.
+fn find_place(path: &str, ips: &[IpAddr]) -> Option<Place> {
+    let geoip = match GeoIp::open(path) {
+        Ok(g) => g,
+        Err(e) => {
+            warn!("{}", e);
+            return None;
+        },
+    };
+
+    for ip in ips {
+        match geoip.lookup(ip) {
+            Ok(Some(place)) => {
+                debug!("{} => {:?}", ip, place);
+                return Some(place);
+            },
+            Ok(None) => debug!("{} is not in GeoIP database", ip),
+            Err(e) => warn!("{}", e),
+        }
+    }
+    None
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::{Detection, LocationDetector, SSID};
+use crate::config::Config;
+use crate::location;
+use crate::BoxResult;
+
+/// Match the SSID of the Wi-Fi network we are connected to against locations
+/// `ssid`, no request is sent to the Internet.
+///
+/// The SSID is asked to NetworkManager by running its `nmcli` command line tool,
+/// then to the kernel with `iw` for each wireless interface listed in
+/// `/proc/net/wireless`.
+pub struct SsidDetector {
+    proc_root: PathBuf,
+}
+
+impl SsidDetector {
+    pub fn new(proc_root: PathBuf) -> SsidDetector {
+        SsidDetector { proc_root }
+    }
+
+    /// Get the SSID of current Wi-Fi network, if connected to any.
+    pub fn current_ssid(&self) -> BoxResult<Option<String>> {
+        match Command::new("nmcli").args(["-t", "-f", "ACTIVE,SSID", "device", "wifi"]).output() {
+            Ok(o) if o.status.success() =>
+                return Ok(parse_nmcli(&String::from_utf8_lossy(&o.stdout))),
+            Ok(o) => debug!("nmcli failed: {}", String::from_utf8_lossy(&o.stderr).trim()),
+            Err(e) => debug!("Cannot run nmcli: {}", e),
+        }
+
+        let interfaces = match wireless_interfaces(&self.proc_root) {
+            Ok(i) => i,
+            Err(e) => bail!("Cannot read wireless interfaces: {}", e),
+        };
+
+        for interface in interfaces {
+            match Command::new("iw").args(["dev", &interface, "link"]).output() {
+                Ok(o) if o.status.success() => {
+                    if let Some(ssid) = parse_iw_link(&String::from_utf8_lossy(&o.stdout)) {
+                        return Ok(Some(ssid));
+                    }
+                },
+                Ok(o) => debug!("iw failed: {}", String::from_utf8_lossy(&o.stderr).trim()),
+                Err(e) => bail!("Cannot get Wi-Fi SSID, neither nmcli nor iw can be run: {}", e),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl LocationDetector for SsidDetector {
+    fn name(&self) -> &str {
+        SSID
+    }
+
+    fn detect(&self, config: &Config, detection: &mut Detection) -> BoxResult<bool> {
+        let ssid = match self.current_ssid()? {
+            Some(s) => s,
+            None => {
+                debug!("Not connected to any Wi-Fi network");
+                return Ok(false);
+            },
+        };
+        detection.ssid = Some(ssid.clone());
+
+        match location::best_match(&config.locations, |l| l.ssid.as_ref() == Some(&ssid)) {
+            Some(l) => {
+                debug!("{} => {}", ssid, l.label());
+                detection.location = Some(l.clone());
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+/// Get the active SSID from `nmcli -t -f ACTIVE,SSID device wifi` output.
+///
+/// Terse output escapes colons and backslashes with a backslash.
+pub fn parse_nmcli(output: &str) -> Option<String> {
+    output.lines()
+        .filter_map(|line| line.strip_prefix("yes:"))
+        .map(|ssid| ssid.replace("\\:", ":").replace("\\\\", "\\"))
+        .find(|ssid| !ssid.is_empty())
+}
+
+/// Get the SSID from `iw dev <interface> link` output.
+pub fn parse_iw_link(output: &str) -> Option<String> {
+    output.lines()
+        .filter_map(|line| line.trim_start().strip_prefix("SSID: "))
+        .map(|ssid| ssid.to_string())
+        .next()
+}
+
+/// List wireless interfaces from `/proc/net/wireless`, the first two lines are
+/// headers.
+pub fn wireless_interfaces(proc_root: &Path) -> io::Result<Vec<String>> {
+    let contents = match fs::read_to_string(proc_root.join("net/wireless")) {
+        Ok(c) => c,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    Ok(contents.lines()
+        .skip(2)
+        .filter_map(|line| line.split(':').next())
+        .map(|interface| interface.trim().to_string())
+        .filter(|interface| !interface.is_empty())
+        .collect())
+}
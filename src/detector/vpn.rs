@@ -0,0 +1,91 @@
+use std::fs;
+use std::path::PathBuf;
+
+use super::{is_vpn_interface, Detection, LocationDetector, VPN};
+use crate::config::Config;
+use crate::BoxResult;
+
+/// Detect whether a VPN is up, from the network interfaces matching
+/// `vpn_interfaces` (such as `tun*` or `wg*`) listed in `/proc/net/dev` and up
+/// according to `/sys/class/net` (Linux only). No request is sent to the
+/// Internet.
+///
+/// While a VPN is up, the public IP is the VPN exit address: the cached status
+/// is kept, or `vpn_status` is set if configured.
+pub struct VpnDetector {
+    proc_root: PathBuf,
+    sys_root: PathBuf,
+    vpn_interfaces: Vec<String>,
+}
+
+impl VpnDetector {
+    pub fn new(proc_root: PathBuf, sys_root: PathBuf, vpn_interfaces: Vec<String>) -> VpnDetector {
+        VpnDetector { proc_root, sys_root, vpn_interfaces }
+    }
+
+    /// Get the first VPN interface, if any is up.
+    pub fn vpn_interface(&self) -> BoxResult<Option<String>> {
+        let dev = match fs::read_to_string(self.proc_root.join("net/dev")) {
+            Ok(d) => d,
+            Err(e) => bail!("Cannot read network interfaces: {}", e),
+        };
+
+        Ok(interfaces(&dev).into_iter()
+            .find(|i| is_vpn_interface(i, &self.vpn_interfaces) && self.is_up(i)))
+    }
+
+    /// Whether the interface is up: administratively (`IFF_UP` flag) and
+    /// operationally. Interfaces whose state cannot be read are taken as up.
+    fn is_up(&self, interface: &str) -> bool {
+        let path = self.sys_root.join("class/net").join(interface);
+        let flags = fs::read_to_string(path.join("flags")).ok()
+            .and_then(|f| u32::from_str_radix(f.trim().trim_start_matches("0x"), 16).ok());
+        let operstate = fs::read_to_string(path.join("operstate")).ok();
+
+        match (flags, operstate) {
+            (None, None) => {
+                debug!("Cannot read {} state, it is taken as up", interface);
+                true
+            },
+            (flags, operstate) => is_up(flags, operstate.as_deref()),
+        }
+    }
+}
+
+impl LocationDetector for VpnDetector {
+    fn name(&self) -> &str {
+        VPN
+    }
+
+    fn detect(&self, config: &Config, detection: &mut Detection) -> BoxResult<bool> {
+        match self.vpn_interface()? {
+            Some(i) => {
+                debug!("VPN interface {} is up", i);
+                detection.vpn = Some(i);
+                detection.ignored = config.vpn_status.is_none();
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+/// Whether an interface is up, from its `flags` and `operstate` in
+/// `/sys/class/net`. VPN interfaces usually have an `unknown` state while up.
+pub fn is_up(flags: Option<u32>, operstate: Option<&str>) -> bool {
+    const IFF_UP: u32 = 0x1;
+
+    let up = flags.is_none_or(|f| f & IFF_UP != 0);
+    let running = operstate.is_none_or(|s| !matches!(s.trim(), "down" | "lowerlayerdown" | "notpresent"));
+    up && running
+}
+
+/// Get interfaces names from `/proc/net/dev` content.
+pub fn interfaces(dev: &str) -> Vec<String> {
+    dev.lines()
+        .skip(2)
+        .filter_map(|l| l.split(':').next())
+        .map(|i| i.trim().to_string())
+        .filter(|i| !i.is_empty())
+        .collect()
+}
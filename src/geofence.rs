@@ -0,0 +1,72 @@
+use std::fmt;
+
+/// Mean Earth radius, in meters.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// A position on Earth, in decimal degrees (WGS 84).
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Position {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// A circle on Earth, such as
+/// `{ latitude = 48.8584, longitude = 2.2945, radius = 200 }`, its radius in
+/// meters.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Geofence {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius: f64,
+}
+
+impl Position {
+    /// Great-circle distance to the other position, in meters.
+    pub fn distance(&self, other: &Position) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+
+        // Haversine formula.
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+    }
+}
+
+impl Geofence {
+    /// Center of the geofence.
+    pub fn center(&self) -> Position {
+        Position { latitude: self.latitude, longitude: self.longitude }
+    }
+
+    /// Whether the position is inside the geofence.
+    pub fn contains(&self, position: &Position) -> bool {
+        self.center().distance(position) <= self.radius
+    }
+
+    /// Describe what is wrong with the geofence, if anything.
+    pub fn check(&self) -> Result<(), String> {
+        if !(-90.0..=90.0).contains(&self.latitude) {
+            return Err(format!("latitude {} is not between -90 and 90", self.latitude));
+        }
+        if !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(format!("longitude {} is not between -180 and 180", self.longitude));
+        }
+        if self.radius.is_nan() || self.radius <= 0.0 {
+            return Err(format!("radius {} is not positive", self.radius));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}", self.latitude, self.longitude)
+    }
+}
+
+impl fmt::Display for Geofence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({} m)", self.center(), self.radius)
+    }
+}
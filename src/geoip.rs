@@ -0,0 +1,73 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+use super::BoxResult;
+
+/// Language of places names.
+const LANGUAGE: &str = "en";
+
+/// Offline GeoIP database, in MaxMind DB format (`.mmdb`) such as GeoLite2 City
+/// or DB-IP City Lite. Addresses are only looked up in the local file.
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+}
+
+/// Where an IP address is located, as found in a GeoIP database.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Place {
+    pub city: Option<String>,
+    pub country: Option<String>,
+    /// ISO 3166-1 alpha-2 country code, such as `FR`.
+    pub country_code: Option<String>,
+}
+
+impl GeoIp {
+    pub fn open<P: AsRef<Path>>(path: P) -> BoxResult<GeoIp> {
+        match Reader::open_readfile(path.as_ref()) {
+            Ok(reader) => Ok(GeoIp { reader }),
+            Err(e) => bail!("Cannot open GeoIP database {}: {}", path.as_ref().display(), e),
+        }
+    }
+
+    /// Find where the address is, if the database knows about it.
+    pub fn lookup(&self, ip: &IpAddr) -> BoxResult<Option<Place>> {
+        let city: geoip2::City = match self.reader.lookup(*ip) {
+            Ok(c) => c,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => return Ok(None),
+            Err(e) => bail!("Cannot look {} up in GeoIP database: {}", ip, e),
+        };
+
+        let place = Place {
+            city: city.city.and_then(|c| name(c.names)),
+            country: city.country.as_ref().and_then(|c| name(c.names.clone())),
+            country_code: city.country.and_then(|c| c.iso_code).map(|c| c.to_string()),
+        };
+
+        if place.city.is_none() && place.country.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(place))
+    }
+}
+
+impl Place {
+    /// Slack emoji of the country flag, such as `:flag-fr:`.
+    pub fn flag(&self) -> Option<String> {
+        self.country_code.as_ref().map(|c| format!(":flag-{}:", c.to_lowercase()))
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.city.iter().chain(self.country.iter()).map(|n| n.as_str()).collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// Name in our language from a names map.
+fn name(names: Option<std::collections::BTreeMap<&str, &str>>) -> Option<String> {
+    names?.get(LANGUAGE).map(|n| n.to_string())
+}
@@ -0,0 +1,177 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use super::{random_bytes, resolve, udp_socket, LookupOptions};
+use crate::BoxResult;
+
+/// DNS resolver answering with our public IP address, such as OpenDNS for
+/// `myip.opendns.com` or Google for `o-o.myaddr.l.google.com` TXT records:
+///
+/// * resolver: Address of the resolver, with an optional port (53 by
+///   default), such as `208.67.222.222` or `resolver1.opendns.com:53`.
+/// * name: Name to query.
+/// * record: Record type to query, `A` (default), `AAAA` or `TXT`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct DnsService {
+    pub resolver: String,
+    pub name: String,
+    #[serde(default)]
+    pub record: RecordType,
+}
+
+/// DNS record types holding an address.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RecordType {
+    #[default]
+    A,
+    Aaaa,
+    Txt,
+}
+
+impl RecordType {
+    fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Aaaa => 28,
+            RecordType::Txt => 16,
+        }
+    }
+}
+
+impl DnsService {
+    /// Ask public IP to the resolver.
+    pub fn request(&self, options: &LookupOptions) -> BoxResult<IpAddr> {
+        let resolver = resolve(&self.resolver, 53, options.family)?;
+        debug!("Requesting public ip to {} for {} {:?}...", resolver, self.name, self.record);
+
+        let socket = udp_socket(resolver, options, options.timeout)?;
+        let id = u16::from_be_bytes(random_bytes::<2>());
+        socket.send(&build_query(id, &self.name, self.record)?)?;
+
+        let mut response = [0; 4096];
+        loop {
+            let len = match socket.recv(&mut response) {
+                Ok(l) => l,
+                Err(e) => bail!("No answer from {}: {}", resolver, e),
+            };
+
+            // Ignore late answers to previous queries.
+            if len >= 2 && u16::from_be_bytes([response[0], response[1]]) == id {
+                return parse_response(&response[..len], self.record);
+            }
+        }
+    }
+}
+
+/// Build a recursive query for one record.
+pub fn build_query(id: u16, name: &str, record: RecordType) -> BoxResult<Vec<u8>> {
+    let mut query = Vec::<u8>::new();
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("Invalid name: {}", name);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+
+    query.extend_from_slice(&record.code().to_be_bytes());
+    // Class IN.
+    query.extend_from_slice(&[0x00, 0x01]);
+    Ok(query)
+}
+
+/// Read the first address from the answers of a response.
+pub fn parse_response(response: &[u8], record: RecordType) -> BoxResult<IpAddr> {
+    if response.len() < 12 {
+        bail!("DNS response too short");
+    }
+
+    let flags = u16::from_be_bytes([response[2], response[3]]);
+    if flags & 0x8000 == 0 {
+        bail!("DNS message is not a response");
+    }
+    if flags & 0x000f != 0 {
+        bail!("DNS error, response code is: {}", flags & 0x000f);
+    }
+
+    let questions = u16::from_be_bytes([response[4], response[5]]);
+    let answers = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(response, offset)? + 4;
+    }
+
+    for _ in 0..answers {
+        offset = skip_name(response, offset)?;
+        let header = match response.get(offset..offset + 10) {
+            Some(h) => h,
+            None => bail!("DNS response truncated"),
+        };
+        let kind = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+        offset += 10;
+
+        let data = match response.get(offset..offset + len) {
+            Some(d) => d,
+            None => bail!("DNS response truncated"),
+        };
+        offset += len;
+
+        if kind != record.code() {
+            continue;
+        }
+
+        match (record, data.len()) {
+            (RecordType::A, 4) =>
+                return Ok(IpAddr::from([data[0], data[1], data[2], data[3]])),
+            (RecordType::Aaaa, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                return Ok(IpAddr::from(octets));
+            },
+            (RecordType::Txt, _) => {
+                if let Some(ip) = txt_strings(data).iter()
+                    .find_map(|s| IpAddr::from_str(s.trim()).ok())
+                {
+                    return Ok(ip);
+                }
+            },
+            _ => bail!("Invalid {:?} record", record),
+        }
+    }
+
+    bail!("No {:?} record holding an address in DNS response", record)
+}
+
+/// Get the offset following a name, which is either a list of labels or ends
+/// with a pointer to another name.
+fn skip_name(message: &[u8], mut offset: usize) -> BoxResult<usize> {
+    loop {
+        match message.get(offset) {
+            Some(0) => return Ok(offset + 1),
+            Some(l) if l & 0xc0 == 0xc0 => return Ok(offset + 2),
+            Some(l) => offset += *l as usize + 1,
+            None => bail!("DNS response truncated"),
+        }
+    }
+}
+
+/// Split TXT record data in its length-prefixed strings.
+fn txt_strings(data: &[u8]) -> Vec<String> {
+    let mut strings = Vec::<String>::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let len = data[offset] as usize;
+        let end = (offset + 1 + len).min(data.len());
+        strings.push(String::from_utf8_lossy(&data[offset + 1..end]).to_string());
+        offset = end;
+    }
+    strings
+}
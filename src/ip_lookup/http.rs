@@ -0,0 +1,248 @@
+use std::io::{Read, Write};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use native_tls::TlsConnector;
+use regex::Regex;
+use reqwest::blocking::Client;
+use reqwest::Url;
+use serde_json::Value;
+
+use super::{resolve, tcp_stream, LookupOptions};
+use crate::BoxResult;
+
+/// Largest answer read from services, in bytes.
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+/// HTTP service answering with our public IP address:
+///
+/// * url: URL to request.
+/// * format: Format of the answer, `text` (default), `json` or `regex`.
+/// * field: Path of the field holding the address in a JSON answer, such as
+///   `ip` (default) or `data.addresses.0`.
+/// * regex: Regular expression matching the address, either its first capture
+///   group or the whole match is used.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct HttpService {
+    pub url: String,
+    #[serde(default)]
+    pub format: ResponseFormat,
+    pub field: Option<String>,
+    pub regex: Option<String>,
+}
+
+/// Format of public IP services answers.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    /// Bare address, surrounding whitespaces are ignored.
+    #[default]
+    Text,
+    /// JSON document, the address is read from `field`.
+    Json,
+    /// Any text, the address is extracted with `regex`.
+    Regex,
+}
+
+impl HttpService {
+    /// Service answering with the bare address.
+    pub fn text(url: &str) -> HttpService {
+        HttpService {
+            url: url.to_string(),
+            format: ResponseFormat::Text,
+            field: None,
+            regex: None,
+        }
+    }
+
+    /// Check the service can be used, such as its regular expression being
+    /// valid.
+    pub fn check(&self) -> Result<(), String> {
+        match (self.format, &self.regex) {
+            (ResponseFormat::Regex, None) =>
+                Err(format!("{}: format is regex but no regex is set", self.url)),
+            (ResponseFormat::Regex, Some(r)) => match Regex::new(r) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("{}: invalid regex: {}", self.url, e)),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Ask public IP to the service.
+    pub fn request(&self, options: &LookupOptions) -> BoxResult<IpAddr> {
+        debug!("Requesting public ip to {}...", self.url);
+
+        let body = match &options.interface {
+            Some(interface) => get_through(&self.url, interface, options)?,
+            None => get(&self.url, options)?,
+        };
+        self.parse_response(&body)
+    }
+
+    /// Read the public IP address from the service answer.
+    pub fn parse_response(&self, body: &str) -> BoxResult<IpAddr> {
+        let address = match self.format {
+            ResponseFormat::Text => body.to_string(),
+            ResponseFormat::Json => {
+                let value: Value = match serde_json::from_str(body) {
+                    Ok(v) => v,
+                    Err(e) => bail!("Cannot deserialize: {}", e),
+                };
+                let field = self.field.as_deref().unwrap_or("ip");
+                match json_field(&value, field) {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(v) => bail!("Field {} is not a string: {}", field, v),
+                    None => bail!("Field {} not found in: {}", field, body.trim()),
+                }
+            },
+            ResponseFormat::Regex => {
+                let regex = match &self.regex {
+                    Some(r) => Regex::new(r)?,
+                    None => bail!("Format is regex but no regex is set"),
+                };
+                match regex.captures(body) {
+                    Some(c) => c.get(1).or_else(|| c.get(0))
+                        .map_or(String::new(), |m| m.as_str().to_string()),
+                    None => bail!("Regex {} does not match: {}", regex, body.trim()),
+                }
+            },
+        };
+
+        match IpAddr::from_str(address.trim()) {
+            Ok(ip) => Ok(ip),
+            Err(e) => bail!("Cannot parse IP {:?}: {}", address.trim(), e),
+        }
+    }
+}
+
+/// Get the body of a successful answer.
+fn get(url: &str, options: &LookupOptions) -> BoxResult<String> {
+    let client = Client::builder()
+        .timeout(options.timeout)
+        .local_address(options.local_address())
+        .build()?;
+    let resp = match client.get(url).send() {
+        Ok(r) => r,
+        Err(e) => bail!(format!("Request error: {}", e)),
+    };
+
+    if !resp.status().is_success() {
+        bail!(format!("Request error, status is: {}", resp.status()));
+    }
+
+    match resp.text() {
+        Ok(b) => Ok(b),
+        Err(e) => bail!(format!("Cannot read response: {}", e)),
+    }
+}
+
+/// Get the body of a successful answer, sending the request through the
+/// network interface. The HTTP client cannot bind to an interface, a bare
+/// HTTP/1.1 request is sent on a bound connection instead.
+fn get_through(url: &str, interface: &str, options: &LookupOptions) -> BoxResult<String> {
+    let url = Url::parse(url)?;
+    let host = match url.host_str() {
+        Some(h) => h,
+        None => bail!("No host in URL {}", url),
+    };
+    let port = url.port_or_known_default().unwrap_or(80);
+    let peer = resolve(&format!("{}:{}", host, port), port, options.family)?;
+    debug!("Requesting {} through {}...", peer, interface);
+
+    let mut target = url.path().to_string();
+    if let Some(query) = url.query() {
+        target = format!("{}?{}", target, query);
+    }
+    let authority = match url.port() {
+        Some(p) => format!("{}:{}", host, p),
+        None => host.to_string(),
+    };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: slack-status\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        target, authority);
+
+    let stream = tcp_stream(peer, options)?;
+    let response = match url.scheme() {
+        "http" => exchange(stream, &request)?,
+        "https" => {
+            let domain = host.trim_start_matches('[').trim_end_matches(']');
+            match TlsConnector::new()?.connect(domain, stream) {
+                Ok(tls) => exchange(tls, &request)?,
+                Err(e) => bail!("TLS error with {}: {}", host, e),
+            }
+        },
+        scheme => bail!("Unsupported URL scheme {}", scheme),
+    };
+
+    let (status, body) = parse_http(&response)?;
+    if !(200..300).contains(&status) {
+        bail!(format!("Request error, status is: {}", status));
+    }
+    Ok(body)
+}
+
+/// Send the request and read the whole answer, until the server closes the
+/// connection.
+fn exchange<S: Read + Write>(mut stream: S, request: &str) -> BoxResult<Vec<u8>> {
+    stream.write_all(request.as_bytes())?;
+    let mut response = Vec::new();
+    if let Err(e) = stream.take(MAX_RESPONSE).read_to_end(&mut response) {
+        bail!(format!("Cannot read response: {}", e));
+    }
+    Ok(response)
+}
+
+/// Read the status code and body of an HTTP/1.1 answer, chunked or not.
+pub fn parse_http(response: &[u8]) -> BoxResult<(u16, String)> {
+    let split = match response.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(s) => s,
+        None => bail!("Invalid HTTP answer, no end of headers"),
+    };
+    let head = String::from_utf8_lossy(&response[..split]);
+    let mut body = &response[split + 4..];
+
+    let status = match head.lines().next().and_then(|l| l.split_whitespace().nth(1)).map(u16::from_str) {
+        Some(Ok(s)) => s,
+        _ => bail!("Invalid HTTP status line: {}", head.lines().next().unwrap_or_default()),
+    };
+    let chunked = head.lines().skip(1)
+        .filter_map(|l| l.split_once(':'))
+        .any(|(name, value)| name.trim().eq_ignore_ascii_case("transfer-encoding")
+            && value.to_ascii_lowercase().contains("chunked"));
+    if !chunked {
+        return Ok((status, String::from_utf8_lossy(body).to_string()));
+    }
+
+    let mut decoded = Vec::new();
+    loop {
+        let end = match body.windows(2).position(|w| w == b"\r\n") {
+            Some(e) => e,
+            None => bail!("Invalid chunked answer"),
+        };
+        let size = String::from_utf8_lossy(&body[..end]);
+        let size = match usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16) {
+            Ok(s) => s,
+            Err(_) => bail!("Invalid chunk size {:?}", size),
+        };
+        body = &body[end + 2..];
+        if size == 0 {
+            break;
+        }
+        if body.len() < size {
+            bail!("Truncated chunked answer");
+        }
+        decoded.extend_from_slice(&body[..size]);
+        body = body.get(size + 2..).unwrap_or_default();
+    }
+    Ok((status, String::from_utf8_lossy(&decoded).to_string()))
+}
+
+/// Get a field from a JSON document by its path, made of object keys and
+/// array indices separated by dots.
+fn json_field<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |v, key| match v {
+        Value::Array(a) => a.get(key.parse::<usize>().ok()?),
+        _ => v.get(key),
+    })
+}
@@ -0,0 +1,401 @@
+//! Public IP address lookup.
+//!
+//! The public IP is asked to the services listed in `ip_request_address`, in
+//! order, the next one is asked when a service fails. In quorum mode, services
+//! are asked until enough of them agree on the address.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::ser::{Serialize, Serializer};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use super::config::Config;
+use super::BoxResult;
+
+pub mod dns;
+pub mod http;
+pub mod stun;
+
+pub use dns::{DnsService, RecordType};
+pub use http::{HttpService, ResponseFormat};
+pub use stun::StunService;
+
+/// Service used when none is configured.
+pub const DEFAULT_IP_SERVICE: &str = "https://ip.clara.net";
+
+/// Request timeout used when none is configured, in seconds.
+pub const DEFAULT_TIMEOUT: u64 = 5;
+
+/// IP address family.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    /// Family of the address.
+    pub fn of(ip: &IpAddr) -> Family {
+        if ip.is_ipv4() { Family::V4 } else { Family::V6 }
+    }
+
+    /// Whether the address belongs to this family.
+    pub fn matches(self, ip: &IpAddr) -> bool {
+        match self {
+            Family::V4 => ip.is_ipv4(),
+            Family::V6 => ip.is_ipv6(),
+        }
+    }
+
+    /// Unspecified address of this family, binding to it forces the family of
+    /// connections.
+    pub fn unspecified(self) -> IpAddr {
+        match self {
+            Family::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            Family::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+}
+
+/// How public IP requests are sent.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LookupOptions {
+    /// Timeout of each request.
+    pub timeout: Duration,
+    /// Address family to use, requests are sent over this family only and the
+    /// answer must be an address of this family.
+    pub family: Option<Family>,
+    /// Local address to send requests from.
+    pub source: Option<IpAddr>,
+    /// Network interface to send requests through, whatever the routing says.
+    pub interface: Option<String>,
+}
+
+impl LookupOptions {
+    /// Options set in configuration for this family, a source interface is
+    /// resolved to its address of this family.
+    pub fn from(config: &Config, family: Option<Family>) -> BoxResult<LookupOptions> {
+        let source = match &config.ip_request_source {
+            Some(s) => Some(source_address(s, family)?),
+            None => None,
+        };
+        let interface = config.ip_request_source.clone()
+            .filter(|s| IpAddr::from_str(s).is_err());
+
+        Ok(LookupOptions {
+            timeout: Duration::from_secs(config.ip_request_timeout.unwrap_or(DEFAULT_TIMEOUT)),
+            family: family.or_else(|| source.as_ref().map(Family::of)),
+            source,
+            interface,
+        })
+    }
+
+    /// Local address to bind requests to.
+    fn local_address(&self) -> Option<IpAddr> {
+        self.source.or_else(|| self.family.map(|f| f.unspecified()))
+    }
+}
+
+/// Get the local address to send requests from, `source` is either an address
+/// or the name of a network interface.
+///
+/// Link-local addresses cannot reach the Internet, they are skipped.
+pub fn source_address(source: &str, family: Option<Family>) -> BoxResult<IpAddr> {
+    if let Ok(ip) = IpAddr::from_str(source) {
+        return match family {
+            Some(f) if !f.matches(&ip) => bail!("Source address {} is not an {:?} address", ip, f),
+            _ => Ok(ip),
+        };
+    }
+
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(i) => i,
+        Err(e) => bail!("Cannot read network interfaces: {}", e),
+    };
+    let ip = interfaces.iter()
+        .filter(|i| i.name == source)
+        .map(|i| i.ip())
+        .filter(|ip| family.is_none_or(|f| f.matches(ip)))
+        .filter(|ip| match ip {
+            IpAddr::V4(ip) => !ip.is_link_local(),
+            IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 != 0xfe80,
+        })
+        .min_by_key(|ip| ip.is_ipv6());
+
+    match ip {
+        Some(ip) => {
+            debug!("Sending requests from {} ({})", ip, source);
+            Ok(ip)
+        },
+        None if interfaces.iter().any(|i| i.name == source) => match family {
+            Some(f) => bail!("Interface {} has no {:?} address", source, f),
+            None => bail!("Interface {} has no address", source),
+        },
+        None => bail!("No interface {}", source),
+    }
+}
+
+/// Check the request source, interfaces can only be bound to on Linux.
+pub fn check_source(source: &str) -> Result<(), String> {
+    if IpAddr::from_str(source).is_ok() {
+        return Ok(());
+    }
+    if !cfg!(target_os = "linux") {
+        return Err(format!("{} is not an address, interface names are only supported on Linux", source));
+    }
+    match source.is_empty() || source.len() > 15 || source.contains(|c: char| c == '/' || c.is_whitespace()) {
+        true => Err(format!("{} is neither an address nor an interface name", source)),
+        false => Ok(()),
+    }
+}
+
+/// A service answering with our public IP address, either the URL of an HTTP
+/// service answering with the bare address, or a table describing an
+/// `HttpService`, a `DnsService` or a `StunService`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum IpService {
+    Url(String),
+    Http(HttpService),
+    Dns(DnsService),
+    Stun(StunService),
+}
+
+impl From<String> for IpService {
+    fn from(url: String) -> IpService {
+        IpService::Url(url)
+    }
+}
+
+impl IpService {
+    /// Service name, as displayed to the user.
+    pub fn describe(&self) -> String {
+        match self {
+            IpService::Url(url) => url.clone(),
+            IpService::Http(s) => s.url.clone(),
+            IpService::Dns(s) => format!("{} ({:?} {})", s.resolver, s.record, s.name),
+            IpService::Stun(s) => format!("stun:{}", s.stun),
+        }
+    }
+
+    /// Same service, described as a table.
+    pub fn described(&self) -> IpService {
+        match self {
+            IpService::Url(url) => IpService::Http(HttpService::text(url)),
+            s => s.clone(),
+        }
+    }
+
+    /// Check the service can be used.
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            IpService::Http(s) => s.check(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ask public IP to the service.
+    pub fn request(&self, options: &LookupOptions) -> BoxResult<IpAddr> {
+        let ip = match self {
+            IpService::Url(url) => HttpService::text(url).request(options)?,
+            IpService::Http(s) => s.request(options)?,
+            IpService::Dns(s) => s.request(options)?,
+            IpService::Stun(s) => s.request(options)?,
+        };
+
+        match options.family {
+            Some(f) if !f.matches(&ip) => bail!("Answer {} is not an {:?} address", ip, f),
+            _ => Ok(ip),
+        }
+    }
+}
+
+/// Get the socket address of a server, either an IP or a host name, with an
+/// optional port. When a family is set, only addresses of this family are
+/// used.
+pub fn resolve(address: &str, default_port: u16, family: Option<Family>) -> BoxResult<SocketAddr> {
+    let addrs = if let Ok(addr) = SocketAddr::from_str(address) {
+        vec![addr]
+    } else if let Ok(ip) = IpAddr::from_str(address) {
+        vec![SocketAddr::new(ip, default_port)]
+    } else {
+        let addrs = if address.contains(':') {
+            address.to_socket_addrs()
+        } else {
+            (address, default_port).to_socket_addrs()
+        };
+        match addrs {
+            Ok(a) => a.collect(),
+            Err(e) => bail!("Cannot resolve {}: {}", address, e),
+        }
+    };
+
+    match addrs.into_iter().find(|a| family.is_none_or(|f| f.matches(&a.ip()))) {
+        Some(addr) => Ok(addr),
+        None => match family {
+            Some(f) => bail!("{} has no {:?} address", address, f),
+            None => bail!("Cannot resolve {}", address),
+        },
+    }
+}
+
+/// Socket to reach a peer, bound to the source address and interface if set.
+fn socket(peer: SocketAddr, options: &LookupOptions, type_: Type, protocol: Protocol) -> BoxResult<Socket> {
+    let domain = if peer.is_ipv4() { Domain::ipv4() } else { Domain::ipv6() };
+    let socket = Socket::new(domain, type_, Some(protocol))?;
+
+    if let Some(interface) = &options.interface {
+        bind_device(&socket, interface)?;
+    }
+    let local = options.source.unwrap_or_else(|| Family::of(&peer.ip()).unspecified());
+    socket.bind(&SockAddr::from(SocketAddr::new(local, 0)))?;
+    Ok(socket)
+}
+
+/// Send packets through this interface only, with `SO_BINDTODEVICE`, so that
+/// they leave through it even while a VPN routes everything.
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &Socket, interface: &str) -> BoxResult<()> {
+    let name = std::ffi::CString::new(interface)?;
+    match socket.bind_device(Some(&name)) {
+        Ok(_) => Ok(()),
+        Err(e) => bail!("Cannot send requests through interface {}: {}", interface, e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_socket: &Socket, interface: &str) -> BoxResult<()> {
+    bail!("Cannot send requests through interface {}, only supported on Linux", interface)
+}
+
+/// UDP socket sending to and receiving from a single peer, from the source
+/// address and interface if set.
+fn udp_socket(peer: SocketAddr, options: &LookupOptions, timeout: Duration) -> BoxResult<UdpSocket> {
+    let socket = socket(peer, options, Type::dgram(), Protocol::udp())?.into_udp_socket();
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(peer)?;
+    Ok(socket)
+}
+
+/// TCP connection to a peer, from the source address and interface if set.
+fn tcp_stream(peer: SocketAddr, options: &LookupOptions) -> BoxResult<TcpStream> {
+    let socket = socket(peer, options, Type::stream(), Protocol::tcp())?;
+    if let Err(e) = socket.connect_timeout(&SockAddr::from(peer), options.timeout) {
+        bail!("Cannot connect to {}: {}", peer, e);
+    }
+
+    let stream = socket.into_tcp_stream();
+    stream.set_read_timeout(Some(options.timeout))?;
+    stream.set_write_timeout(Some(options.timeout))?;
+    Ok(stream)
+}
+
+/// Unpredictable bytes to identify requests, randomly seeded hashers are good
+/// enough for that.
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    for chunk in bytes.chunks_mut(8) {
+        let random = RandomState::new().build_hasher().finish().to_be_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    bytes
+}
+
+/// Write services as bare URLs when none needs a description, every service is
+/// described otherwise, TOML arrays cannot mix strings and tables.
+pub fn serialize_services<S: Serializer>(services: &[IpService], serializer: S) -> Result<S::Ok, S::Error> {
+    if services.iter().all(|s| matches!(s, IpService::Url(_))) {
+        services.serialize(serializer)
+    } else {
+        let services: Vec<IpService> = services.iter().map(|s| s.described()).collect();
+        services.serialize(serializer)
+    }
+}
+
+/// Get current public IP address from configured services, over any family.
+pub fn get_public_ip(config: &Config) -> BoxResult<IpAddr> {
+    lookup(config, &LookupOptions::from(config, None)?)
+}
+
+/// Get current public IPv4 and IPv6 addresses from configured services, each
+/// family is requested on its own. Fails only if both fail.
+pub fn get_public_ips(config: &Config) -> BoxResult<Vec<IpAddr>> {
+    let mut ips = Vec::<IpAddr>::new();
+    let mut errors = Vec::<String>::new();
+
+    for family in &[Family::V4, Family::V6] {
+        match LookupOptions::from(config, Some(*family)).and_then(|o| lookup(config, &o)) {
+            Ok(ip) => ips.push(ip),
+            Err(e) => {
+                info!("No public {:?} address: {}", family, e);
+                errors.push(format!("{:?}: {}", family, e));
+            },
+        }
+    }
+
+    if ips.is_empty() {
+        bail!(errors.join(", "));
+    }
+    Ok(ips)
+}
+
+/// Get current public IP address from configured services.
+pub fn lookup(config: &Config, options: &LookupOptions) -> BoxResult<IpAddr> {
+    let services = if config.ip_request_address.is_empty() {
+        vec![IpService::Url(DEFAULT_IP_SERVICE.to_string())]
+    } else {
+        config.ip_request_address.clone()
+    };
+
+    let names: Vec<String> = services.iter().map(|s| s.describe()).collect();
+    let answers = names.iter().zip(services.iter())
+        .map(|(name, s)| (name.as_str(), s.request(options)));
+
+    agree(answers, config.ip_quorum.unwrap_or(1))
+}
+
+/// Get the first address given by `quorum` services, services are only asked
+/// until then.
+pub fn agree<'a, I>(answers: I, quorum: usize) -> BoxResult<IpAddr>
+    where I: Iterator<Item = (&'a str, BoxResult<IpAddr>)>
+{
+    let mut votes = HashMap::<IpAddr, Vec<&str>>::new();
+    let mut errors = Vec::<String>::new();
+
+    for (service, answer) in answers {
+        match answer {
+            Ok(ip) => {
+                info!("Public IP {} given by {}", ip, service);
+                let voters = votes.entry(ip).or_default();
+                voters.push(service);
+
+                if voters.len() >= quorum {
+                    if quorum > 1 {
+                        info!("Public IP {} confirmed by {}", ip, voters.join(", "));
+                    }
+                    return Ok(ip);
+                }
+            },
+            Err(e) => {
+                warn!("Cannot get public IP from {}: {}", service, e);
+                errors.push(format!("{}: {}", service, e));
+            },
+        }
+    }
+
+    if votes.is_empty() {
+        bail!("Every service failed ({})", errors.join(", "));
+    }
+
+    let mut votes: Vec<_> = votes.into_iter().collect();
+    votes.sort();
+    let answers: Vec<String> = votes.iter()
+        .map(|(ip, voters)| format!("{} from {}", ip, voters.join(", ")))
+        .collect();
+    bail!("Less than {} services agree on public IP ({})", quorum, answers.join("; "))
+}
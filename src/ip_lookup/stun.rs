@@ -0,0 +1,142 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::{random_bytes, resolve, udp_socket, LookupOptions};
+use crate::BoxResult;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Requests are sent again when unanswered, UDP packets can be lost.
+const ATTEMPTS: u32 = 3;
+
+/// STUN server (RFC 5389) telling the address our requests come from, which
+/// is not changed by transparent HTTP proxies:
+///
+/// * stun: Address of the server, with an optional port (3478 by default),
+///   such as `stun.l.google.com:19302`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct StunService {
+    pub stun: String,
+}
+
+impl StunService {
+    /// Ask public IP to the server with a binding request.
+    pub fn request(&self, options: &LookupOptions) -> BoxResult<IpAddr> {
+        let server = resolve(&self.stun, 3478, options.family)?;
+        debug!("Requesting public ip to STUN server {}...", server);
+
+        let socket = udp_socket(server, options, options.timeout / ATTEMPTS)?;
+        let transaction = random_bytes::<12>();
+        let request = binding_request(&transaction);
+
+        let mut response = [0; 1024];
+        for attempt in 1..=ATTEMPTS {
+            socket.send(&request)?;
+
+            loop {
+                let len = match socket.recv(&mut response) {
+                    Ok(l) => l,
+                    Err(e) => {
+                        debug!("No answer from {} (attempt {}): {}", server, attempt, e);
+                        break;
+                    },
+                };
+
+                // Ignore late answers to previous requests.
+                if len >= 20 && response[8..20] == transaction {
+                    return parse_response(&response[..len], &transaction);
+                }
+            }
+        }
+
+        bail!("No answer from STUN server {}", server)
+    }
+}
+
+/// Build a binding request without attributes.
+pub fn binding_request(transaction: &[u8; 12]) -> Vec<u8> {
+    let mut request = Vec::<u8>::with_capacity(20);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(transaction);
+    request
+}
+
+/// Read our address from a binding response, XOR-MAPPED-ADDRESS is preferred
+/// over MAPPED-ADDRESS sent by old servers.
+pub fn parse_response(response: &[u8], transaction: &[u8; 12]) -> BoxResult<IpAddr> {
+    if response.len() < 20 {
+        bail!("STUN response too short");
+    }
+
+    let kind = u16::from_be_bytes([response[0], response[1]]);
+    if kind != BINDING_SUCCESS {
+        bail!("STUN error, message type is: {:#06x}", kind);
+    }
+    if response[4..8] != MAGIC_COOKIE.to_be_bytes() || response[8..20] != transaction[..] {
+        bail!("STUN response does not match request");
+    }
+
+    let len = u16::from_be_bytes([response[2], response[3]]) as usize;
+    let attributes = match response.get(20..20 + len) {
+        Some(a) => a,
+        None => bail!("STUN response truncated"),
+    };
+
+    let mut mapped = None;
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let kind = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let len = u16::from_be_bytes([attributes[offset + 2], attributes[offset + 3]]) as usize;
+        let value = match attributes.get(offset + 4..offset + 4 + len) {
+            Some(v) => v,
+            None => bail!("STUN response truncated"),
+        };
+
+        match kind {
+            XOR_MAPPED_ADDRESS => return read_address(value, Some(transaction)),
+            MAPPED_ADDRESS => mapped = Some(read_address(value, None)?),
+            _ => {},
+        }
+
+        // Attributes are padded to 4 bytes.
+        offset += 4 + len.div_ceil(4) * 4;
+    }
+
+    match mapped {
+        Some(ip) => Ok(ip),
+        None => bail!("No mapped address in STUN response"),
+    }
+}
+
+/// Read an address attribute, XORed with the magic cookie and the transaction
+/// ID when set.
+fn read_address(value: &[u8], xor: Option<&[u8; 12]>) -> BoxResult<IpAddr> {
+    let mut mask = [0; 16];
+    if let Some(transaction) = xor {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction);
+    }
+
+    match (value.get(1), value.len()) {
+        (Some(0x01), 8) => {
+            let mut octets = [0; 4];
+            for (i, o) in octets.iter_mut().enumerate() {
+                *o = value[4 + i] ^ mask[i];
+            }
+            Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+        },
+        (Some(0x02), 20) => {
+            let mut octets = [0; 16];
+            for (i, o) in octets.iter_mut().enumerate() {
+                *o = value[4 + i] ^ mask[i];
+            }
+            Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+        },
+        _ => bail!("Invalid address attribute in STUN response"),
+    }
+}
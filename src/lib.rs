@@ -8,21 +8,46 @@ extern crate serde_json;
 extern crate simple_error;
 
 pub mod cache;
+pub mod caldav;
+pub mod calendar;
 pub mod config;
+pub mod detector;
+pub mod geofence;
+pub mod geoip;
+pub mod ip_lookup;
 pub mod location;
+pub mod network;
+pub mod ooo;
+pub mod rules;
+pub mod schedule;
+pub mod script;
+pub mod stability;
+pub mod template;
 
 use std::error::Error;
 use std::net::IpAddr;
-use std::str::FromStr;
+use std::path::PathBuf;
 
 use chrono::prelude::*;
-use chrono::Duration;
 use reqwest::blocking::*;
 use serde_json::Value;
 
-pub use cache::{Cache, StatusCache};
-pub use config::{Config, StatusConfig};
+pub use cache::{Cache, PendingLocation, StatusCache};
+pub use caldav::CalDav;
+pub use calendar::{Calendar, Ics, Meeting};
+pub use config::{Config, StatusConfig, Transition};
+pub use detector::{Detection, LocationDetector};
+pub use geofence::{Geofence, Position};
+pub use geoip::{GeoIp, Place};
+pub use ip_lookup::IpService;
 pub use location::Location;
+pub use network::Network;
+pub use ooo::OutOfOffice;
+pub use rules::{Condition, Presence, Rule, Signals};
+pub use schedule::{Schedule, ScheduleRule};
+pub use script::Script;
+pub use stability::Stability;
+pub use template::{Template, Variables};
 
 pub type BoxResult<T> = Result<T,Box<dyn Error>>;
 pub type ReqwestResult = Result<reqwest::blocking::Response, reqwest::Error>;
@@ -30,20 +55,46 @@ pub type ReqwestResult = Result<reqwest::blocking::Response, reqwest::Error>;
 pub struct SlackStatus<'a> {
     client: Client,
     pub config: &'a Config,
+    /// Status cache file, at the default OS location if not set.
+    cache_file: Option<PathBuf>,
+}
+
+/// Where a status comes from, see `SlackStatus::status_with`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Source {
+    OutOfOffice,
+    Rule,
+    Meeting,
+    /// Location is ignored, the status last set is kept.
+    Cache,
+    Schedule,
+    Transition,
+    Vpn,
+    /// Location status, or unknown location status.
+    Location,
+    Script,
 }
 
 impl<'a> SlackStatus<'a> {
-    pub fn from(config: &'a Config) -> BoxResult<SlackStatus> {
+    pub fn from(config: &'a Config) -> BoxResult<SlackStatus<'a>> {
         if config.token.is_empty() {
             bail!("You must copy your Slack token to configuration file.");
         };
 
         Ok(SlackStatus {
             client: Client::new(),
-            config: config,
+            config,
+            cache_file: None,
         })
     }
 
+    /// Client keeping its status cache in this file, so that tests do not
+    /// touch the user cache.
+    #[doc(hidden)]
+    pub fn with_cache_file(config: &'a Config, cache_file: PathBuf) -> BoxResult<SlackStatus<'a>> {
+        Ok(SlackStatus { cache_file: Some(cache_file), ..SlackStatus::from(config)? })
+    }
+
     /// Request current Slack status.
     pub fn get_slack_status(&self) -> BoxResult<Option<StatusCache>>{
         debug!("Requesting Slack status...");
@@ -71,39 +122,30 @@ impl<'a> SlackStatus<'a> {
         Ok(Some(StatusCache {
             text: text.trim_matches('"').to_string(),
             emoji: emoji.trim_matches('"').to_string(),
-            expiration: expiration,
+            expiration,
         }))
     }
 
-    /// Set Slack status.
-    pub fn set_slack_status(&self, status: &StatusConfig, manually_set: bool) -> BoxResult<()> {
+    /// Set Slack status, returns whether it was updated: automatic updates are
+    /// skipped while a status set manually has not expired.
+    pub fn set_slack_status(&self, status: &StatusConfig, manually_set: bool) -> BoxResult<bool> {
+        let cache_file = self.read_cache();
+
         // If the status have been set manually and haven't expired yet, then
         // it won't be automatically updated.
         if !manually_set {
-            let cache_file = match Cache::read() {
-                Ok(c) => c,
-                Err(e) => {
-                    error!("Cannot read cache: {}", e);
-                    None
-                },
-            };
-
-            if let Some(cache) = cache_file {
+            if let Some(cache) = &cache_file {
                 if cache.manually_set &&
                     (cache.status.expiration > Utc::now().timestamp())
                 {
                     info!("Status set manually, too soon to update automatically.");
-                    return Ok(())
+                    return Ok(false)
                 }
             }
         }
 
         debug!("Updating Slack status...");
-        let expiration = match status.expire_after_hours {
-            Some(h) =>
-                Utc::now().timestamp() + Duration::hours(h).num_seconds(),
-            None => 0,
-        };
+        let expiration = status.expiration_at(Utc::now().timestamp()).unwrap_or(0);
         let data = json!({
                     "profile": {
                         "status_text": status.text,
@@ -124,94 +166,513 @@ impl<'a> SlackStatus<'a> {
             status: StatusCache {
                 text: status.text.clone(),
                 emoji: status.emoji.clone(),
-                expiration: expiration,
+                expiration,
             },
-            manually_set: manually_set,
+            manually_set,
+            ..cache_file.unwrap_or_default()
         };
-        cache.save()?;
+        self.write_cache(&cache)?;
 
-        Ok(())
+        Ok(true)
     }
 
-    // TODO: UX: make it clear when status come from cache.
+    /// Read the cache, errors are logged and no cache is used instead.
+    pub fn read_cache(&self) -> Option<Cache> {
+        let cache = match &self.cache_file {
+            Some(path) => Cache::read_from(path),
+            None => Cache::read(),
+        };
+        match cache {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Cannot read cache: {}", e);
+                None
+            },
+        }
+    }
 
-    /// Compute Slack status based on current location.
-    pub fn status_from(&self, ip: &IpAddr) -> StatusConfig {
-        // Check if location is set to be ignored, in that case get status from
-        // cache
-        if self.config.ignore_ips.iter().any(|i| i == ip) {
-            let cache_file = match Cache::read() {
-                Ok(c) => c,
+    /// Save the cache.
+    fn write_cache(&self, cache: &Cache) -> BoxResult<()> {
+        match &self.cache_file {
+            Some(path) => cache.save_to(path),
+            None => cache.save(),
+        }
+    }
+
+    /// Save the cache, errors are logged.
+    fn save_cache(&self, cache: &Cache) {
+        if let Err(e) = self.write_cache(cache) {
+            error!("Cannot save cache: {}", e);
+        }
+    }
+
+    /// Location detectors, in configuration order.
+    pub fn detectors(&self) -> Vec<Box<dyn LocationDetector + '_>> {
+        let mut detectors = Vec::<Box<dyn LocationDetector>>::new();
+        for name in &self.config.detectors {
+            match name.as_str() {
+                detector::PUBLIC_IP =>
+                    detectors.push(Box::new(detector::PublicIpDetector::new(self))),
+                detector::SSID =>
+                    detectors.push(Box::new(detector::SsidDetector::new(self.config.proc_root()))),
+                detector::GATEWAY_MAC =>
+                    detectors.push(Box::new(detector::GatewayMacDetector::new(self.config.proc_root()))),
+                detector::LOCAL_NETWORK =>
+                    detectors.push(Box::new(detector::LocalNetworkDetector::new(self.config.vpn_interfaces.clone()))),
+                detector::GPS =>
+                    detectors.push(Box::new(detector::GpsDetector::new(self.config.gpsd_address()))),
+                detector::VPN =>
+                    detectors.push(Box::new(detector::VpnDetector::new(
+                        self.config.proc_root(), self.config.sys_root(), self.config.vpn_interfaces.clone()))),
+                _ => error!("Unknown location detector: {}", name),
+            }
+        }
+        detectors
+    }
+
+    /// Detect current location with configured detectors.
+    pub fn detect(&self) -> BoxResult<Detection> {
+        self.detect_with(&self.detectors())
+    }
+
+    /// Detect current location, asking each detector in turn until one is
+    /// confident. Fails only if every detector failed.
+    pub fn detect_with(&self, detectors: &[Box<dyn LocationDetector + '_>]) -> BoxResult<Detection> {
+        let mut detection = Detection::default();
+        let mut errors = Vec::<String>::new();
+
+        for d in detectors {
+            debug!("Detecting location with {}...", d.name());
+            match d.detect(self.config, &mut detection) {
+                Ok(true) => {
+                    detection.detector = Some(d.name().to_string());
+                    return Ok(detection);
+                },
+                Ok(false) => debug!("No location found by {}", d.name()),
                 Err(e) => {
-                    error!("Cannot read cache: {}", e);
-                    None
+                    warn!("Location detector {} failed: {}", d.name(), e);
+                    errors.push(format!("{}: {}", d.name(), e));
                 },
-            };
+            }
+        }
 
-            if let Some(cache) = cache_file {
-                return StatusConfig {
-                    text: cache.status.text,
-                    emoji: cache.status.emoji,
+        if !detectors.is_empty() && errors.len() == detectors.len() {
+            bail!(errors.join(", "));
+        }
+
+        Ok(detection)
+    }
+
+    /// Check whether the detected location has been stable long enough to
+    /// switch status to it, see `Config.stability`. The location waiting to be
+    /// stable is recorded in cache.
+    ///
+    /// Ignored locations are always stable, the cached status is kept anyway.
+    pub fn is_stable(&self, detection: &Detection) -> bool {
+        let stability = match &self.config.stability {
+            Some(s) if !detection.ignored => s,
+            _ => return true,
+        };
+
+        let mut cache = match self.read_cache() {
+            Some(c) => c,
+            None => return true,
+        };
+
+        let candidate = detection.location_name();
+        let stable = stability.settle(cache.location.as_deref(), &mut cache.pending, &candidate, Utc::now().timestamp());
+
+        self.save_cache(&cache);
+        stable
+    }
+
+    /// Hold back a new location until it is stable, see `Config.stability`:
+    /// until then the previous location is set back in the detection, so that
+    /// its status is kept while meetings, schedule and rules still apply.
+    /// Returns whether the detected location is kept.
+    pub fn stabilize(&self, detection: &mut Detection) -> bool {
+        if self.is_stable(detection) {
+            return true;
+        }
+        let previous = match self.read_cache().and_then(|c| c.location) {
+            Some(p) => p,
+            None => return true,
+        };
+
+        info!("Location {} is not stable yet, {} is kept.", detection.location_name(), previous);
+        detection.location = self.config.locations.iter().find(|l| l.label() == previous).cloned();
+        detection.vpn = match previous == detector::ON_VPN {
+            true => detection.vpn.take().or(Some(previous)),
+            false => None,
+        };
+        false
+    }
+
+    /// Record in cache when a known location is detected. Within the grace
+    /// period after that, unknown locations are ignored so that the last known
+    /// location status is kept, see `Config.grace_period`.
+    pub fn apply_grace_period(&self, detection: &mut Detection) {
+        if self.config.grace_period.is_none() || detection.ignored || detection.vpn.is_some() {
+            return;
+        }
+
+        let mut cache = match self.read_cache() {
+            Some(c) => c,
+            None => return,
+        };
+
+        let now = Utc::now().timestamp();
+        if detection.location.is_some() {
+            cache.last_seen = Some(now);
+            self.save_cache(&cache);
+        } else if self.config.in_grace_period(cache.last_seen, now) {
+            info!("Unknown location within grace period, previous status is kept.");
+            detection.ignored = true;
+        }
+    }
+
+    /// Whether a known location has been seen within the grace period, in that
+    /// case location detection failures are not reported.
+    pub fn in_grace_period(&self) -> bool {
+        self.read_cache()
+            .is_some_and(|c| self.config.in_grace_period(c.last_seen, Utc::now().timestamp()))
+    }
+
+    /// Status of the out-of-office range active at this time, if any.
+    pub fn ooo_status(&self, now: DateTime<Utc>) -> Option<StatusConfig> {
+        self.ooo_template(now).map(|s| self.render(s, &Detection::default(), now))
+    }
+
+    /// Status template of the out-of-office range active at this time.
+    fn ooo_template(&self, now: DateTime<Utc>) -> Option<StatusConfig> {
+        let timezone = self.config.timezone();
+        self.config.ooo.iter()
+            .find(|o| o.is_active(now, timezone))
+            .map(|o| o.status(timezone))
+    }
+
+    /// Meetings going on or starting in the next 24 hours, the calendar is
+    /// read once per run. Calendar errors are only logged, no meetings are
+    /// returned then.
+    pub fn meetings(&self, now: DateTime<Utc>) -> Vec<Meeting> {
+        let calendar = match &self.config.calendar {
+            Some(c) => c,
+            None => return Vec::new(),
+        };
+        match calendar.load(self.config.timezone()) {
+            Ok(ics) => ics.meetings(now, now + chrono::Duration::days(1)),
+            Err(e) => {
+                warn!("Cannot read calendar: {}", e);
+                Vec::new()
+            },
+        }
+    }
+
+    /// Status template of the meeting going on at this time, if any.
+    pub fn meeting_status(&self, meetings: &[Meeting], now: DateTime<Utc>) -> Option<StatusConfig> {
+        self.config.calendar.as_ref()?.status_at(meetings, now)
+    }
+
+    /// Whether the out-of-office status is already set, automatic updates are
+    /// suspended until it expires.
+    pub fn is_ooo_status_set(&self, status: &StatusConfig) -> bool {
+        self.read_cache().is_some_and(|c| c.status.text == status.text && c.status.emoji == status.emoji
+            && Some(c.status.expiration) == status.expiration)
+    }
+
+    /// Remember the detected location in cache once status is set for it, it
+    /// is the previous location on next run, and whether the status set comes
+    /// from a transition.
+    pub fn remember_location(&self, detection: &Detection, source: Source) {
+        if detection.ignored {
+            return;
+        }
+
+        let mut cache = match self.read_cache() {
+            Some(c) => c,
+            None => return,
+        };
+
+        cache.transition = source == Source::Transition;
+        cache.location = Some(detection.location_name());
+        self.save_cache(&cache);
+    }
+
+    /// Status on transition from the previous location, as read in cache, to
+    /// the detected one: the first matching `transitions` rule, or the
+    /// `on_leave` status of the previous location when the detected one is
+    /// unknown.
+    ///
+    /// At the same location, the transition status already set is kept until
+    /// it expires.
+    pub fn transition_status(&self, cache: &Cache, detection: &Detection, now: i64) -> Option<StatusConfig> {
+        let previous = cache.location.as_ref()?;
+        let current = detection.location_name();
+
+        if *previous == current {
+            if cache.transition && (cache.status.expiration == 0 || cache.status.expiration > now) {
+                // Already rendered, it is not rendered again.
+                return Some(StatusConfig {
+                    text: template::escape(&cache.status.text),
+                    emoji: template::escape(&cache.status.emoji),
                     expire_after_hours: None,
-                }
+                    expiration: Some(cache.status.expiration),
+                });
             }
+            return None;
         }
 
-        // Else try to get location from location
-        match self.status_from_location(ip) {
-            Some(status) => status,
-            None => self.config.defaults.clone().unwrap_or(StatusConfig {
-                text: "commuting".to_string(),
-                emoji: ":mountain_railway:".to_string(),
-                expire_after_hours: Some(1),
-            }),
+        if let Some(t) = self.config.transitions.iter().find(|t| t.matches(previous, &current)) {
+            debug!("Transition from {} to {}", previous, current);
+            return Some(t.status());
+        }
+
+        if detection.location.is_none() && detection.vpn.is_none() {
+            let left = self.config.locations.iter().find(|l| l.label() == *previous)?;
+            debug!("Leaving {}", previous);
+            return left.on_leave.clone();
         }
+        None
     }
 
-    /// Get status from configured locations and current public IP.
-    pub fn status_from_location(&self, ip: &IpAddr) -> Option<StatusConfig> {
-        let statuses: Vec<&Location> = self.config.locations.iter()
-            .filter(|l| l.ip == *ip)
-            .collect();
-
-        match statuses.len() {
-            0 => None,
-            1 => {
-                debug!("{} => {}", ip, statuses[0].text);
-                Some(StatusConfig {
-                    text: statuses[0].text.clone(),
-                    emoji: statuses[0].emoji.clone(),
-                    expire_after_hours: statuses[0].expire_after_hours.clone(),
-                })
-            },
-            _ => {
-                error!("Configuration error: several locations match your IP!");
+    /// Whether statuses depend on transitions between locations.
+    fn has_transitions(&self) -> bool {
+        !self.config.transitions.is_empty()
+            || self.config.locations.iter().any(|l| l.on_leave.is_some())
+    }
+
+    // TODO: UX: make it clear when status come from cache.
+
+    /// Compute Slack status based on detected location.
+    ///
+    /// Statuses are chosen in this order:
+    ///
+    /// 1. out-of-office status,
+    /// 2. first matching rule,
+    /// 3. meeting status, from the calendar,
+    /// 4. cached status when location is ignored,
+    /// 5. first matching schedule rule,
+    /// 6. transition from the previous location,
+    /// 7. VPN status,
+    /// 8. location status, or unknown location status.
+    ///
+    /// The status script, if any, has the last word.
+    pub fn status_from(&self, detection: &Detection) -> StatusConfig {
+        self.status_at(detection, Utc::now())
+    }
+
+    /// Compute Slack status based on detected location at this time.
+    pub fn status_at(&self, detection: &Detection, now: DateTime<Utc>) -> StatusConfig {
+        self.status_with(detection, &self.meetings(now), now).0
+    }
+
+    /// Compute Slack status based on detected location and meetings (see
+    /// `meetings`) at this time, and where it comes from.
+    pub fn status_with(&self, detection: &Detection, meetings: &[Meeting], now: DateTime<Utc>) -> (StatusConfig, Source) {
+        let (status, source) = self.template_at(detection, meetings, now);
+
+        // Out of office, automatic updates are suspended.
+        let script = self.config.script.as_ref().filter(|_| source != Source::OutOfOffice);
+        let (status, source) = match script.and_then(|s| self.script_status(s, detection, meetings, &status, now)) {
+            Some(s) => (s, Source::Script),
+            None => (status, source),
+        };
+        (self.render(status, detection, now), source)
+    }
+
+    /// Status from the script, `None` if it keeps the built-in one or fails.
+    fn script_status(
+        &self,
+        script: &Script,
+        detection: &Detection,
+        meetings: &[Meeting],
+        status: &StatusConfig,
+        now: DateTime<Utc>,
+    ) -> Option<StatusConfig> {
+        let context = script::Context {
+            signals: self.signals(detection, meetings, now),
+            cached: self.read_cache().map(|c| c.status),
+            meetings: meetings.to_vec(),
+            status: status.clone(),
+        };
+        match script.run(&context) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("{}, built-in status is used", e);
                 None
             },
         }
     }
 
-    /// Get current public IP address.
-    pub fn get_public_ip(&self) -> BoxResult<IpAddr> {
-        let url = match &self.config.ip_request_address {
-            Some(u) => u.clone(),
-            None => "https://ip.clara.net".to_string(),
-        };
+    /// Replace template variables in status text and emoji.
+    pub fn render(&self, status: StatusConfig, detection: &Detection, now: DateTime<Utc>) -> StatusConfig {
+        let variables = self.variables(&status, detection, now);
+        StatusConfig {
+            text: template::render(&status.text, &variables),
+            emoji: template::render(&status.emoji, &variables),
+            ..status
+        }
+    }
+
+    /// Values of template variables for a status, see `Variables`.
+    pub fn variables(&self, status: &StatusConfig, detection: &Detection, now: DateTime<Utc>) -> Variables {
+        let timezone = self.config.timezone();
+        Variables {
+            location: detection.location_name(),
+            place: detection.place.clone(),
+            end_of_day: self.config.schedule.clone().unwrap_or_default().end_of_day(now),
+            expiration: status.expiration_at(now.timestamp())
+                .and_then(|e| Utc.timestamp_opt(e, 0).single())
+                .map(|e| template::in_timezone(e, timezone)),
+            ..Variables::at(now, timezone)
+        }
+    }
+
+    /// Values of the signals rules conditions are made of.
+    pub fn signals(&self, detection: &Detection, meetings: &[Meeting], now: DateTime<Utc>) -> Signals {
+        Signals {
+            location: detection.location_name(),
+            ips: detection.ips.clone(),
+            ssid: detection.ssid.clone(),
+            place: detection.place.clone(),
+            vpn: detection.vpn.is_some(),
+            ignored: detection.ignored,
+            meeting: calendar::meeting_at(meetings, now).is_some(),
+            now: template::in_timezone(now, self.config.timezone()),
+        }
+    }
+
+    /// First rule matching at this time, if any.
+    pub fn matching_rule(&self, detection: &Detection, meetings: &[Meeting], now: DateTime<Utc>) -> Option<&Rule> {
+        if self.config.rules.is_empty() {
+            return None;
+        }
+        let signals = self.signals(detection, meetings, now);
+        self.config.rules.iter().find(|r| r.matches(&signals))
+    }
+
+    /// Set Slack presence and Do Not Disturb as the rule says, if it does.
+    pub fn apply_rule(&self, rule: &Rule) -> BoxResult<()> {
+        if let Some(presence) = rule.presence {
+            debug!("Setting presence to {:?}...", presence);
+            let presence = if presence == Presence::Away { "away" } else { "auto" };
+            self.post("users.setPresence", &[("presence", presence.to_string())])?;
+        }
+        if let Some(minutes) = rule.dnd_minutes {
+            debug!("Pausing notifications for {} minutes...", minutes);
+            self.post("dnd.setSnooze", &[("num_minutes", minutes.to_string())])?;
+        }
+        Ok(())
+    }
+
+    /// Call a Slack API method, failing if Slack does not answer ok.
+    fn post(&self, method: &str, params: &[(&str, String)]) -> BoxResult<()> {
+        let res = self.client.post(&format!("https://slack.com/api/{}", method))
+            .bearer_auth(&self.config.token)
+            .form(params)
+            .send()?;
+        let body: Value = res.json()?;
+        debug!("{}: {}", method, body);
 
-        debug!("Requesting public ip to {}...", url);
+        match body["ok"].as_bool() {
+            Some(true) => Ok(()),
+            _ => bail!("Slack {} error: {}", method, body["error"].as_str().unwrap_or("unknown")),
+        }
+    }
+
+    /// Status template, before variables are replaced, and where it comes
+    /// from.
+    fn template_at(&self, detection: &Detection, meetings: &[Meeting], now: DateTime<Utc>) -> (StatusConfig, Source) {
+        if let Some(status) = self.ooo_template(now) {
+            return (status, Source::OutOfOffice);
+        }
+        if let Some(status) = self.matching_rule(detection, meetings, now)
+            .and_then(|r| r.status(&template::in_timezone(now, self.config.timezone())))
+        {
+            return (status, Source::Rule);
+        }
+        if let Some(status) = self.meeting_status(meetings, now) {
+            return (status, Source::Meeting);
+        }
 
-        let resp = match self.client.get(url.as_str()).send() {
-            Ok(r) => r,
-            Err(e) => bail!(format!("Request error: {}", e)),
+        // Cache is only needed to keep status or compare with previous
+        // location.
+        let cache_file = if detection.ignored || self.has_transitions() {
+            self.read_cache()
+        } else {
+            None
         };
 
-        if resp.status().is_success() {
-            return match IpAddr::from_str(&resp.text().unwrap()) {
-                Ok(ip) => Ok::<IpAddr, Box<dyn Error>>(ip),
-                Err(e) => bail!(format!("Cannot parse IP: {}", e)),
+        // Check if location is set to be ignored, in that case get status from
+        // cache
+        if detection.ignored {
+            if let Some(cache) = cache_file {
+                // Already rendered.
+                return (StatusConfig {
+                    text: template::escape(&cache.status.text),
+                    emoji: template::escape(&cache.status.emoji),
+                    expire_after_hours: None,
+                    expiration: None,
+                }, Source::Cache)
             }
         }
 
-        bail!(format!("Request error, status is: {}", resp.status()))
+        if let Some(status) = self.config.schedule.as_ref()
+            .and_then(|s| s.status_at(now, &detection.location_name()))
+        {
+            return (status, Source::Schedule);
+        }
+
+        // Moving from a location to another.
+        if let Some(status) = cache_file.and_then(|c| self.transition_status(&c, detection, now.timestamp())) {
+            return (status, Source::Transition);
+        }
+
+        if let (Some(_), Some(status)) = (&detection.vpn, &self.config.vpn_status) {
+            return (status.clone(), Source::Vpn);
+        }
+
+        // Else use detected location status, or describe where the unknown
+        // location is from GeoIP database.
+        let status = match (&detection.location, &detection.place) {
+            (Some(location), _) => location.status(),
+            (None, Some(_)) => self.config.geoip_status(),
+            (None, None) => self.config.defaults.clone().unwrap_or(StatusConfig {
+                text: "commuting".to_string(),
+                emoji: ":mountain_railway:".to_string(),
+                expire_after_hours: Some(1),
+                expiration: None,
+            }),
+        };
+        (status, Source::Location)
+    }
+
+    /// Get status from configured locations and current public IP, of either
+    /// family.
+    ///
+    /// When several locations match, the one with the highest priority wins,
+    /// then the one with the most specific network.
+    pub fn status_from_location(&self, ip: &IpAddr) -> Option<StatusConfig> {
+        location::best_match(&self.config.locations, |l| l.matches_ip(ip))
+            .map(|l| l.status())
+    }
+
+    /// Get status from configured locations geofences and a GPS position.
+    ///
+    /// When several geofences contain the position, the one with the highest
+    /// priority wins, then the one with the nearest center.
+    pub fn status_from_position(&self, position: &Position) -> Option<StatusConfig> {
+        detector::gps::nearest_geofence(&self.config.locations, position)
+            .map(|l| l.status())
+    }
+
+    /// Get current public IP address.
+    pub fn get_public_ip(&self) -> BoxResult<IpAddr> {
+        ip_lookup::get_public_ip(self.config)
+    }
+
+    /// Get current public IPv4 and IPv6 addresses, either one may be missing.
+    pub fn get_public_ips(&self) -> BoxResult<Vec<IpAddr>> {
+        ip_lookup::get_public_ips(self.config)
     }
 }
@@ -1,17 +1,189 @@
+use std::cmp::Ordering;
 use std::fmt;
 use std::net::IpAddr;
 
-/// A Location matches an IP address (either IPv4 or IPv6) with a Status.
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+use super::config::StatusConfig;
+use super::geofence::Geofence;
+use super::network::Network;
+
+/// A Location matches some aspects of the environment with a Status:
+///
+/// * ip: public IP network (either IPv4 or IPv6), it can be a single address
+///   (`203.0.113.17`) or a prefix (`203.0.113.16/28`, `2001:db8:1::/64`),
+///   useful when the public IP rotates in a pool of addresses.
+/// * ip6: public IPv6 network, for dual-stack locations identified by both
+///   their IPv4 and IPv6 addresses. The location matches on either one.
+/// * ssid: Wi-Fi network name.
+/// * gateway_mac: MAC address of the default gateway, such as
+///   `00:11:22:33:44:55`.
+/// * local_network: network of a local interface, such as `10.12.0.0/16`.
+/// * geofence: circle containing the GPS position, such as
+///   `{ latitude = 48.8584, longitude = 2.2945, radius = 200 }` (radius in
+///   meters). When several geofences contain the position, the one with the
+///   nearest center wins.
+///
+/// Each location detector looks at the key it knows about, a location can have
+/// several keys.
+///
+/// The `on_leave` status is set when leaving the location for an unknown one,
+/// unless a `Config.transitions` rule matches.
+///
+/// When several locations match, the one with the highest `priority` wins (0
+/// if not set), then the one with the most specific network (fewest host bits
+/// in `ip`, `ip6` or `local_network`).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
 pub struct Location {
-    pub ip: IpAddr,
+    pub name: Option<String>,
+    pub ip: Option<Network>,
+    pub ip6: Option<Network>,
+    pub ssid: Option<String>,
+    pub gateway_mac: Option<String>,
+    pub local_network: Option<Network>,
+    pub geofence: Option<Geofence>,
+    pub priority: Option<i64>,
     pub text: String,
     pub emoji: String,
     pub expire_after_hours: Option<i64>,
+    pub on_leave: Option<StatusConfig>,
+}
+
+impl Location {
+    /// Name of the location if any, its keys otherwise.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(n) => n.clone(),
+            None => self.keys(),
+        }
+    }
+
+    /// Keys identifying the location, as displayed to the user.
+    pub fn keys(&self) -> String {
+        let keys = self.common_keys(self);
+        if keys.is_empty() {
+            "<no key>".to_string()
+        } else {
+            keys.join(", ")
+        }
+    }
+
+    /// Whether one of the location public networks contains this IP.
+    pub fn matches_ip(&self, ip: &IpAddr) -> bool {
+        self.ip.iter().chain(self.ip6.iter()).any(|n| n.contains(ip))
+    }
+
+    /// Whether the location gateway MAC address is this one.
+    pub fn matches_gateway_mac(&self, mac: &str) -> bool {
+        self.gateway_mac.as_ref().is_some_and(|m| normalize_mac(m) == normalize_mac(mac))
+    }
+
+    /// Whether the location local network contains this local IP.
+    pub fn matches_local_ip(&self, ip: &IpAddr) -> bool {
+        self.local_network.is_some_and(|n| n.contains(ip))
+    }
+
+    /// Whether the location has no key, and thus never matches.
+    pub fn has_no_key(&self) -> bool {
+        self.ip.is_none() && self.ip6.is_none() && self.ssid.is_none() && self.gateway_mac.is_none()
+            && self.local_network.is_none() && self.geofence.is_none()
+    }
+
+    /// Keys shared by both locations, for which both would match at the same
+    /// time.
+    pub fn common_keys(&self, other: &Location) -> Vec<String> {
+        let mut keys = Vec::<String>::new();
+        if let (Some(a), Some(b)) = (&self.ip, &other.ip) {
+            if a == b {
+                keys.push(a.to_string());
+            }
+        }
+        if let (Some(a), Some(b)) = (&self.ip6, &other.ip6) {
+            if a == b {
+                keys.push(a.to_string());
+            }
+        }
+        if let (Some(a), Some(b)) = (&self.ssid, &other.ssid) {
+            if a == b {
+                keys.push(format!("SSID {}", a));
+            }
+        }
+        if let (Some(a), Some(b)) = (&self.gateway_mac, &other.gateway_mac) {
+            if normalize_mac(a) == normalize_mac(b) {
+                keys.push(format!("gateway {}", normalize_mac(a)));
+            }
+        }
+        if let (Some(a), Some(b)) = (&self.local_network, &other.local_network) {
+            if a == b {
+                keys.push(format!("LAN {}", a));
+            }
+        }
+        if let (Some(a), Some(b)) = (&self.geofence, &other.geofence) {
+            if a == b {
+                keys.push(format!("geofence {}", a));
+            }
+        }
+        keys
+    }
+
+    /// Status to set at this location.
+    pub fn status(&self) -> StatusConfig {
+        StatusConfig {
+            text: self.text.clone(),
+            emoji: self.emoji.clone(),
+            expire_after_hours: self.expire_after_hours,
+            expiration: None,
+        }
+    }
+
+    /// Compare locations by precedence, the greatest wins when both match.
+    pub fn precedence(&self, other: &Location) -> Ordering {
+        self.priority.unwrap_or(0).cmp(&other.priority.unwrap_or(0))
+            .then(other.host_bits().cmp(&self.host_bits()))
+    }
+
+    /// Host bits of the most specific network, networks of both families are
+    /// compared this way.
+    fn host_bits(&self) -> u8 {
+        self.ip.iter().chain(self.ip6.iter()).chain(self.local_network.iter())
+            .map(|n| n.host_bits())
+            .min()
+            .unwrap_or(u8::MAX)
+    }
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} => {} {}", self.ip, self.emoji, self.text)
+        match &self.name {
+            Some(n) => write!(f, "{} ({}) => {} {}", n, self.keys(), self.emoji, self.text),
+            None => write!(f, "{} => {} {}", self.keys(), self.emoji, self.text),
+        }
     }
 }
+
+/// Find the location with the highest precedence among those matching.
+///
+/// Ties are resolved using configuration order, but it is reported as a
+/// configuration error.
+pub fn best_match<F>(locations: &[Location], matches: F) -> Option<&Location>
+    where F: Fn(&Location) -> bool
+{
+    let mut candidates: Vec<&Location> = locations.iter()
+        .filter(|l| matches(l))
+        .collect();
+
+    // Stable sort, configuration order is kept for ties.
+    candidates.sort_by(|a, b| b.precedence(a));
+
+    let location = candidates.first()?;
+    if candidates.len() > 1 && candidates[1].precedence(location) == Ordering::Equal {
+        error!("Configuration error: locations {} and {} both match, using {}.",
+            location.label(), candidates[1].label(), location.label());
+    }
+
+    Some(location)
+}
+
+/// Write a MAC address lowercase with colon separators, so that
+/// `00-11-22-AA-BB-CC` and `00:11:22:aa:bb:cc` are equal.
+pub fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_lowercase().replace('-', ":")
+}
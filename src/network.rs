@@ -0,0 +1,98 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use ipnet::IpNet;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+/// An IP network (either IPv4 or IPv6), such as `203.0.113.16/28` or
+/// `2001:db8:1::/64`.
+///
+/// A single address is a network with a full-length prefix, it is read from
+/// and written to configuration file without its prefix so that configurations
+/// written with single IPs are still valid.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Network(IpNet);
+
+impl Network {
+    /// Create network from an address and a prefix length, host bits of the
+    /// address are cleared.
+    pub fn new(ip: IpAddr, prefix_len: u8) -> Result<Network, String> {
+        match IpNet::new(ip, prefix_len) {
+            Ok(n) => Ok(Network(n.trunc())),
+            Err(e) => Err(format!("Invalid prefix length {}: {}", prefix_len, e)),
+        }
+    }
+
+    /// Check if the address belongs to this network.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+
+    /// Network address.
+    pub fn addr(&self) -> IpAddr {
+        self.0.network()
+    }
+
+    /// Length of the network prefix, in bits.
+    pub fn prefix_len(&self) -> u8 {
+        self.0.prefix_len()
+    }
+
+    /// Number of host bits, the fewer the more specific the network, whatever
+    /// its family.
+    pub fn host_bits(&self) -> u8 {
+        self.0.max_prefix_len() - self.0.prefix_len()
+    }
+
+    /// Whether the network is a single address.
+    pub fn is_host(&self) -> bool {
+        self.0.prefix_len() == self.0.max_prefix_len()
+    }
+}
+
+impl From<IpAddr> for Network {
+    fn from(ip: IpAddr) -> Network {
+        Network(IpNet::from(ip))
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Network, String> {
+        let s = s.trim();
+        if let Ok(ip) = IpAddr::from_str(s) {
+            return Ok(Network::from(ip));
+        }
+
+        match IpNet::from_str(s) {
+            Ok(n) => Ok(Network(n.trunc())),
+            Err(_) => Err(format!("Invalid IP address or network: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_host() {
+            write!(f, "{}", self.0.addr())
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+impl Serialize for Network {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Network {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Network, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Network::from_str(&s).map_err(de::Error::custom)
+    }
+}
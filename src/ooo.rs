@@ -0,0 +1,71 @@
+use chrono::prelude::*;
+use chrono::Days;
+use chrono_tz::Tz;
+
+use super::config::StatusConfig;
+
+/// Out-of-office date range, from the first to the last day off (inclusive),
+/// such as `{ from = "2026-12-20", to = "2027-01-04" }`.
+///
+/// While the range is active, automatic updates are suspended and its status is
+/// set until the end of the range.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct OutOfOffice {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    #[serde(default = "default_text")]
+    pub text: String,
+    #[serde(default = "default_emoji")]
+    pub emoji: String,
+}
+
+fn default_text() -> String {
+    "out of office".to_string()
+}
+
+fn default_emoji() -> String {
+    ":palm_tree:".to_string()
+}
+
+impl OutOfOffice {
+    pub fn new(from: NaiveDate, to: NaiveDate, text: Option<String>, emoji: Option<String>) -> OutOfOffice {
+        OutOfOffice {
+            from,
+            to,
+            text: text.unwrap_or_else(default_text),
+            emoji: emoji.unwrap_or_else(default_emoji),
+        }
+    }
+
+    /// Start and end timestamps of the range, from midnight of the first day
+    /// to midnight after the last one, in the timezone (system timezone if
+    /// not set).
+    pub fn range(&self, timezone: Option<Tz>) -> (i64, i64) {
+        (midnight(self.from, timezone), midnight(self.to + Days::new(1), timezone))
+    }
+
+    /// Whether the range is active at this time.
+    pub fn is_active(&self, now: DateTime<Utc>, timezone: Option<Tz>) -> bool {
+        let (start, end) = self.range(timezone);
+        start <= now.timestamp() && now.timestamp() < end
+    }
+
+    /// Status to set, expiring at the end of the range.
+    pub fn status(&self, timezone: Option<Tz>) -> StatusConfig {
+        StatusConfig {
+            text: self.text.clone(),
+            emoji: self.emoji.clone(),
+            expire_after_hours: None,
+            expiration: Some(self.range(timezone).1),
+        }
+    }
+}
+
+/// Timestamp of the beginning of the day.
+fn midnight(date: NaiveDate, timezone: Option<Tz>) -> i64 {
+    let time = date.and_time(NaiveTime::MIN);
+    match timezone {
+        Some(tz) => tz.from_local_datetime(&time).earliest().map(|t| t.timestamp()),
+        None => Local.from_local_datetime(&time).earliest().map(|t| t.timestamp()),
+    }.unwrap_or_else(|| time.and_utc().timestamp())
+}
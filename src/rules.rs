@@ -0,0 +1,633 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::net::IpAddr;
+use std::str::{Chars, FromStr};
+
+use chrono::prelude::*;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use super::config::StatusConfig;
+use super::geoip::Place;
+use super::network::Network;
+
+/// Status rule, the first one whose condition holds wins:
+///
+/// * name: Name of the rule, its condition is shown if not set.
+/// * when: Condition, such as `location == "office" && weekday`, see
+///   `Condition`.
+/// * text, emoji: Status to set. Status is computed as without rules if not
+///   set, the rule only sets presence and Do Not Disturb.
+/// * expire_after_hours: Status expires after some hours.
+/// * until: Status expires at this time (`HH:MM`), tomorrow if it is already
+///   past.
+/// * presence: Slack presence to set, `auto` or `away`.
+/// * dnd_minutes: Pause notifications (Do Not Disturb) for some minutes,
+///   renewed at each run while the rule matches.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Rule {
+    pub name: Option<String>,
+    pub when: Condition,
+    pub text: Option<String>,
+    pub emoji: Option<String>,
+    pub expire_after_hours: Option<i64>,
+    pub until: Option<String>,
+    pub presence: Option<Presence>,
+    pub dnd_minutes: Option<u32>,
+}
+
+/// Slack presence.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Presence {
+    /// Online when Slack is used.
+    Auto,
+    Away,
+}
+
+/// Condition of a rule, a boolean expression of signals such as
+/// `location == "office" && weekday && time in 09:00-18:00`:
+///
+/// * `location`: detected location name, `unknown` when no location is found
+///   or `vpn` while a VPN is up.
+/// * `ip`: public IP addresses, to check with `ip in "10.0.0.0/8"`.
+/// * `ssid`: Wi-Fi network name, empty if unknown.
+/// * `city`, `country`, `country_code`: where the public IP is, from the
+///   GeoIP database, empty if unknown.
+/// * `vpn`: whether a VPN is up.
+/// * `ignored`: whether the public IP is ignored.
+/// * `meeting`: whether a meeting is going on, from the calendar.
+/// * `time`: current time, compared with times such as `12:00` or checked with
+///   `time in 12:00-13:30` (ranges may span midnight).
+/// * `day`: day of week, `mon` to `sun`.
+/// * `weekday`, `weekend`: whether it is Monday to Friday, or Saturday and
+///   Sunday.
+///
+/// Strings are compared with `==` and `!=`, or checked with `day in ["sat",
+/// "sun"]`. Times are compared with `==`, `!=`, `<`, `<=`, `>` and `>=`.
+/// Conditions are combined with `!`, `&&`, `||` and parentheses.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+/// Values of the signals conditions are made of, see `Condition`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Signals {
+    pub location: String,
+    pub ips: Vec<IpAddr>,
+    pub ssid: Option<String>,
+    pub place: Option<Place>,
+    pub vpn: bool,
+    pub ignored: bool,
+    pub meeting: bool,
+    /// Current time, in the schedule timezone.
+    pub now: DateTime<FixedOffset>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Type {
+    Bool,
+    Str,
+    Time,
+    Ip,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Type::Bool => "a boolean",
+            Type::Str => "a string",
+            Type::Time => "a time",
+            Type::Ip => "IP addresses",
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Value {
+    Bool(bool),
+    Str(String),
+    Time(NaiveTime),
+    Ips(Vec<IpAddr>),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Expr {
+    Value(Value),
+    Signal(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Op, Box<Expr>, Box<Expr>),
+    InRange(Box<Expr>, NaiveTime, NaiveTime),
+    InList(Box<Expr>, Vec<String>),
+    InNetwork(Box<Expr>, Network),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Time(NaiveTime),
+    Not,
+    And,
+    Or,
+    Op(Op),
+    In,
+    Minus,
+    Comma,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+}
+
+/// Signals and their types.
+const SIGNALS: [(&str, Type); 13] = [
+    ("location", Type::Str),
+    ("ip", Type::Ip),
+    ("ssid", Type::Str),
+    ("city", Type::Str),
+    ("country", Type::Str),
+    ("country_code", Type::Str),
+    ("vpn", Type::Bool),
+    ("ignored", Type::Bool),
+    ("meeting", Type::Bool),
+    ("time", Type::Time),
+    ("day", Type::Str),
+    ("weekday", Type::Bool),
+    ("weekend", Type::Bool),
+];
+
+impl Rule {
+    /// Name of the rule, or its condition.
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.when.to_string())
+    }
+
+    /// Whether the rule applies.
+    pub fn matches(&self, signals: &Signals) -> bool {
+        self.when.eval(signals)
+    }
+
+    /// Status to set, if the rule sets one.
+    pub fn status(&self, now: &DateTime<FixedOffset>) -> Option<StatusConfig> {
+        if self.text.is_none() && self.emoji.is_none() {
+            return None;
+        }
+
+        Some(StatusConfig {
+            text: self.text.clone().unwrap_or_default(),
+            emoji: self.emoji.clone().unwrap_or_default(),
+            expire_after_hours: self.expire_after_hours,
+            expiration: self.until.as_deref().and_then(|u| until(now, u)).map(|t| t.timestamp()),
+        })
+    }
+
+    /// Describe what is wrong with the rule, if anything.
+    pub fn check(&self) -> Result<(), String> {
+        match self.until.as_deref().map(parse_time) {
+            Some(Err(e)) => Err(format!("rule \"{}\": until: {}", self.label(), e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Next time it is `time`.
+fn until(now: &DateTime<FixedOffset>, time: &str) -> Option<DateTime<FixedOffset>> {
+    let time = parse_time(time).ok()?;
+    let mut date = now.date_naive();
+    if time <= now.time() {
+        date = date.succ_opt()?;
+    }
+    now.timezone().from_local_datetime(&date.and_time(time)).single()
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| format!("invalid time (HH:MM): {}", time))
+}
+
+impl Signals {
+    fn get(&self, name: &str) -> Value {
+        let place = self.place.as_ref();
+        let day = self.now.weekday();
+        let weekend = day == Weekday::Sat || day == Weekday::Sun;
+
+        match name {
+            "location" => Value::Str(self.location.clone()),
+            "ip" => Value::Ips(self.ips.clone()),
+            "ssid" => Value::Str(self.ssid.clone().unwrap_or_default()),
+            "city" => Value::Str(place.and_then(|p| p.city.clone()).unwrap_or_default()),
+            "country" => Value::Str(place.and_then(|p| p.country.clone()).unwrap_or_default()),
+            "country_code" => Value::Str(place.and_then(|p| p.country_code.clone()).unwrap_or_default()),
+            "vpn" => Value::Bool(self.vpn),
+            "ignored" => Value::Bool(self.ignored),
+            "meeting" => Value::Bool(self.meeting),
+            "time" => Value::Time(self.now.time()),
+            "day" => Value::Str(day.to_string().to_lowercase()),
+            "weekday" => Value::Bool(!weekend),
+            "weekend" => Value::Bool(weekend),
+            _ => Value::Bool(false),
+        }
+    }
+}
+
+impl Condition {
+    /// Whether the condition holds.
+    pub fn eval(&self, signals: &Signals) -> bool {
+        self.expr.eval(signals) == Value::Bool(true)
+    }
+}
+
+impl Expr {
+    /// Type of the expression, checking its operands.
+    fn check(&self) -> Result<Type, String> {
+        let expect = |e: &Expr, t: Type, what: &str| match e.check()? {
+            found if found == t => Ok(()),
+            found => Err(format!("{} expects {}, found {}", what, t, found)),
+        };
+
+        match self {
+            Expr::Value(Value::Bool(_)) => Ok(Type::Bool),
+            Expr::Value(Value::Str(_)) => Ok(Type::Str),
+            Expr::Value(Value::Time(_)) => Ok(Type::Time),
+            Expr::Value(Value::Ips(_)) => Ok(Type::Ip),
+            Expr::Signal(name) => match SIGNALS.iter().find(|(n, _)| n == name) {
+                Some((_, t)) => Ok(*t),
+                None => Err(format!("unknown signal \"{}\", expected one of: {}",
+                    name, SIGNALS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", "))),
+            },
+            Expr::Not(e) => expect(e, Type::Bool, "!").map(|_| Type::Bool),
+            Expr::And(a, b) => {
+                expect(a, Type::Bool, "&&")?;
+                expect(b, Type::Bool, "&&").map(|_| Type::Bool)
+            },
+            Expr::Or(a, b) => {
+                expect(a, Type::Bool, "||")?;
+                expect(b, Type::Bool, "||").map(|_| Type::Bool)
+            },
+            Expr::Compare(op, a, b) => {
+                let t = a.check()?;
+                expect(b, t, "comparison")?;
+                match (op, t) {
+                    (_, Type::Ip) => Err("IP addresses are checked with \"ip in\" a network".to_string()),
+                    (Op::Eq, _) | (Op::Ne, _) | (_, Type::Time) => Ok(Type::Bool),
+                    _ => Err(format!("only times can be ordered, found {}", t)),
+                }
+            },
+            Expr::InRange(e, _, _) => expect(e, Type::Time, "in a time range").map(|_| Type::Bool),
+            Expr::InList(e, _) => expect(e, Type::Str, "in a list").map(|_| Type::Bool),
+            Expr::InNetwork(e, _) => expect(e, Type::Ip, "in a network").map(|_| Type::Bool),
+        }
+    }
+
+    fn eval(&self, signals: &Signals) -> Value {
+        let truth = |e: &Expr| e.eval(signals) == Value::Bool(true);
+
+        Value::Bool(match self {
+            Expr::Value(v) => return v.clone(),
+            Expr::Signal(name) => return signals.get(name),
+            Expr::Not(e) => !truth(e),
+            Expr::And(a, b) => truth(a) && truth(b),
+            Expr::Or(a, b) => truth(a) || truth(b),
+            Expr::Compare(op, a, b) => {
+                let (a, b) = (a.eval(signals), b.eval(signals));
+                match (op, &a, &b) {
+                    (Op::Eq, _, _) => a == b,
+                    (Op::Ne, _, _) => a != b,
+                    (_, Value::Time(a), Value::Time(b)) => match op {
+                        Op::Lt => a < b,
+                        Op::Le => a <= b,
+                        Op::Gt => a > b,
+                        _ => a >= b,
+                    },
+                    _ => false,
+                }
+            },
+            Expr::InRange(e, from, to) => match e.eval(signals) {
+                Value::Time(t) if from <= to => *from <= t && t < *to,
+                // Range spanning midnight.
+                Value::Time(t) => *from <= t || t < *to,
+                _ => false,
+            },
+            Expr::InList(e, list) => match e.eval(signals) {
+                Value::Str(s) => list.contains(&s),
+                _ => false,
+            },
+            Expr::InNetwork(e, network) => match e.eval(signals) {
+                Value::Ips(ips) => ips.iter().any(|ip| network.contains(ip)),
+                _ => false,
+            },
+        })
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Time(t) => write!(f, "{}", t.format("%H:%M")),
+            Token::Not => write!(f, "!"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Op(op) => write!(f, "{}", match op {
+                Op::Eq => "==",
+                Op::Ne => "!=",
+                Op::Lt => "<",
+                Op::Le => "<=",
+                Op::Gt => ">",
+                Op::Ge => ">=",
+            }),
+            Token::In => write!(f, "in"),
+            Token::Minus => write!(f, "-"),
+            Token::Comma => write!(f, ","),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+        }
+    }
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        Some(t) => format!("\"{}\"", t),
+        None => "the end".to_string(),
+    }
+}
+
+/// Split a condition in tokens.
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                chars.next();
+                continue;
+            },
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(format!("unclosed string \"{}", s)),
+                    }
+                }
+                Token::Str(s)
+            },
+            '0'..='9' => {
+                let time = take_while(&mut chars, |c| c.is_ascii_digit() || c == ':');
+                Token::Time(parse_time(&time)?)
+            },
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let word = take_while(&mut chars, |c| c.is_ascii_alphanumeric() || c == '_');
+                match word.as_str() {
+                    "in" => Token::In,
+                    _ => Token::Ident(word),
+                }
+            },
+            _ => {
+                chars.next();
+                let next = chars.peek().copied();
+                let mut pair = |token| {
+                    chars.next();
+                    token
+                };
+                match (c, next) {
+                    ('&', Some('&')) => pair(Token::And),
+                    ('|', Some('|')) => pair(Token::Or),
+                    ('=', Some('=')) => pair(Token::Op(Op::Eq)),
+                    ('!', Some('=')) => pair(Token::Op(Op::Ne)),
+                    ('<', Some('=')) => pair(Token::Op(Op::Le)),
+                    ('>', Some('=')) => pair(Token::Op(Op::Ge)),
+                    ('<', _) => Token::Op(Op::Lt),
+                    ('>', _) => Token::Op(Op::Gt),
+                    ('!', _) => Token::Not,
+                    ('-', _) => Token::Minus,
+                    (',', _) => Token::Comma,
+                    ('(', _) => Token::LParen,
+                    (')', _) => Token::RParen,
+                    ('[', _) => Token::LBracket,
+                    (']', _) => Token::RBracket,
+                    _ => return Err(format!("unexpected character '{}'", c)),
+                }
+            },
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn take_while(chars: &mut Peekable<Chars>, f: impl Fn(char) -> bool) -> String {
+    let mut s = String::new();
+    while let Some(&c) = chars.peek().filter(|c| f(**c)) {
+        s.push(c);
+        chars.next();
+    }
+    s
+}
+
+/// Recursive descent parser of conditions:
+///
+/// ```text
+/// or         := and ("||" and)*
+/// and        := not ("&&" not)*
+/// not        := "!" not | comparison
+/// comparison := operand (op operand | "in" set)?
+/// operand    := signal | string | time | "true" | "false" | "(" or ")"
+/// set        := time "-" time | string | "[" string ("," string)* "]"
+/// ```
+///
+/// Expressions are at most `MAX_DEPTH` levels deep, so that neither parsing
+/// nor evaluating them overflows the stack.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+/// Maximum depth of condition expressions: nested parentheses, negations and
+/// operators.
+const MAX_DEPTH: usize = 64;
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(t) if t == expected => Ok(()),
+            found => Err(format!("expected \"{}\", found {}", expected, describe(found.as_ref()))),
+        }
+    }
+
+    /// Go one level deeper in the expression.
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err(format!("condition is nested too deeply (more than {} levels)", MAX_DEPTH)),
+            false => Ok(()),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            self.nest()?;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut expr = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            self.nest()?;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            self.nest()?;
+            let expr = Expr::Not(Box::new(self.not()?));
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.operand()?;
+        match self.peek() {
+            Some(Token::Op(op)) => {
+                let op = *op;
+                self.next();
+                Ok(Expr::Compare(op, Box::new(left), Box::new(self.operand()?)))
+            },
+            Some(Token::In) => {
+                self.next();
+                self.set(left)
+            },
+            _ => Ok(left),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(match name.as_str() {
+                "true" => Expr::Value(Value::Bool(true)),
+                "false" => Expr::Value(Value::Bool(false)),
+                _ => Expr::Signal(name),
+            }),
+            Some(Token::Str(s)) => Ok(Expr::Value(Value::Str(s))),
+            Some(Token::Time(t)) => Ok(Expr::Value(Value::Time(t))),
+            Some(Token::LParen) => {
+                self.nest()?;
+                let expr = self.or()?;
+                self.expect(Token::RParen)?;
+                self.depth -= 1;
+                Ok(expr)
+            },
+            found => Err(format!("expected a signal or a value, found {}", describe(found.as_ref()))),
+        }
+    }
+
+    fn set(&mut self, left: Expr) -> Result<Expr, String> {
+        let left = Box::new(left);
+        match self.next() {
+            Some(Token::Time(from)) => {
+                self.expect(Token::Minus)?;
+                match self.next() {
+                    Some(Token::Time(to)) => Ok(Expr::InRange(left, from, to)),
+                    found => Err(format!("expected the end of the time range, found {}", describe(found.as_ref()))),
+                }
+            },
+            Some(Token::Str(s)) => Ok(Expr::InNetwork(left, Network::from_str(&s)?)),
+            Some(Token::LBracket) => {
+                let mut list = Vec::new();
+                loop {
+                    match self.next() {
+                        Some(Token::Str(s)) => list.push(s),
+                        found => return Err(format!("expected a string, found {}", describe(found.as_ref()))),
+                    }
+                    match self.next() {
+                        Some(Token::Comma) => (),
+                        Some(Token::RBracket) => break,
+                        found => return Err(format!("expected ',' or ']', found {}", describe(found.as_ref()))),
+                    }
+                }
+                Ok(Expr::InList(left, list))
+            },
+            found => Err(format!("expected a time range, a network or a list, found {}", describe(found.as_ref()))),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Condition, String> {
+        let error = |e| format!("invalid condition {:?}: {}", s, e);
+
+        let mut parser = Parser { tokens: tokenize(s).map_err(error)?, position: 0, depth: 0 };
+        let expr = parser.or().map_err(error)?;
+        if let Some(t) = parser.peek() {
+            return Err(error(format!("unexpected {}", describe(Some(t)))));
+        }
+        match expr.check().map_err(error)? {
+            Type::Bool => Ok(Condition { source: s.to_string(), expr }),
+            t => Err(error(format!("expected a boolean, found {}", t))),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Serialize for Condition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Condition, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Condition::from_str(&s).map_err(de::Error::custom)
+    }
+}
@@ -0,0 +1,177 @@
+use std::str::FromStr;
+
+use chrono::prelude::*;
+use chrono_tz::Tz;
+
+use super::config::StatusConfig;
+use super::template::in_timezone;
+
+/// Default end of the working day.
+const END_OF_DAY: &str = "18:00";
+
+/// Weekly schedule, statuses depending on time:
+///
+/// * timezone: IANA name of the timezone times are written in, such as
+///   `Europe/Paris` (system timezone if not set).
+/// * end_of_day: End of the working day (`HH:MM`, 18:00 by default), for the
+///   `{end_of_day}` template variable.
+/// * rules: Ordered rules, the first matching one wins, see `ScheduleRule`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Schedule {
+    pub timezone: Option<String>,
+    pub end_of_day: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<ScheduleRule>,
+}
+
+/// Status to set during a time range:
+///
+/// * days: Days of week (`mon`, `tue`...) or ranges of days (`mon-fri`), every
+///   day if not set. Days are checked against current day, even in a time
+///   range spanning midnight.
+/// * from, to: Time range (`HH:MM`), the whole day if not set. A range
+///   spanning midnight, such as 18:00 to 09:00, is allowed.
+/// * location: Only at this location, named as in `Location.label`, or
+///   `unknown` when no location is found.
+///
+/// Status expires at the end of the time range (or of the day), unless
+/// `expire_after_hours` is set.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ScheduleRule {
+    pub days: Option<Vec<String>>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub location: Option<String>,
+    pub text: String,
+    pub emoji: String,
+    pub expire_after_hours: Option<i64>,
+}
+
+impl Schedule {
+    /// Status of the first rule matching at this time and location, if any.
+    pub fn status_at(&self, now: DateTime<Utc>, location: &str) -> Option<StatusConfig> {
+        let timezone = match self.timezone() {
+            Ok(tz) => tz,
+            Err(e) => {
+                error!("{}", e);
+                return None;
+            },
+        };
+
+        let now = in_timezone(now, timezone);
+
+        let (rule, end) = self.rules.iter()
+            .filter(|r| r.location.as_ref().is_none_or(|l| l == location))
+            .find_map(|r| Some((r, r.end(&now)?)))?;
+        debug!("Schedule rule \"{}\" matches until {:?}", rule.text, end);
+
+        Some(StatusConfig {
+            text: rule.text.clone(),
+            emoji: rule.emoji.clone(),
+            expire_after_hours: rule.expire_after_hours,
+            expiration: match rule.expire_after_hours {
+                Some(_) => None,
+                None => end.map(|e| e.timestamp()),
+            },
+        })
+    }
+
+    /// Timezone of the schedule, system timezone if not set.
+    pub fn timezone(&self) -> Result<Option<Tz>, String> {
+        match &self.timezone {
+            Some(tz) => match Tz::from_str(tz) {
+                Ok(tz) => Ok(Some(tz)),
+                Err(_) => Err(format!("Unknown timezone: {}", tz)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// End of the working day including this time.
+    pub fn end_of_day(&self, now: DateTime<Utc>) -> Option<DateTime<FixedOffset>> {
+        let now = in_timezone(now, self.timezone().ok().flatten());
+        let time = parse_time(self.end_of_day.as_deref().unwrap_or(END_OF_DAY)).ok()?;
+        now.timezone().from_local_datetime(&now.date_naive().and_time(time)).single()
+    }
+
+    /// Describe what is wrong with the schedule, if anything.
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = Vec::<String>::new();
+        if let Err(e) = self.timezone() {
+            errors.push(format!("schedule: {}", e));
+        }
+        if let Some(Err(e)) = self.end_of_day.as_deref().map(parse_time) {
+            errors.push(format!("schedule end_of_day: {}", e));
+        }
+        for rule in &self.rules {
+            if let Err(e) = rule.days() {
+                errors.push(format!("schedule rule \"{}\": {}", rule.text, e));
+            }
+            for time in rule.from.iter().chain(rule.to.iter()) {
+                if let Err(e) = parse_time(time) {
+                    errors.push(format!("schedule rule \"{}\": {}", rule.text, e));
+                }
+            }
+        }
+        errors
+    }
+}
+
+impl ScheduleRule {
+    /// Whether the rule matches at this time, with the end of its time range
+    /// (midnight when it ends with the day, `None` if it cannot be told).
+    fn end(&self, now: &DateTime<FixedOffset>) -> Option<Option<DateTime<FixedOffset>>> {
+        let days = self.days().ok()?;
+        if !days.is_empty() && !days.contains(&now.weekday()) {
+            return None;
+        }
+
+        let from = self.from.as_deref().map(parse_time).transpose().ok()?;
+        let to = self.to.as_deref().map(parse_time).transpose().ok()?;
+        let time = now.time();
+        let today = now.date_naive();
+        let at = |date: NaiveDate, t: NaiveTime| {
+            now.timezone().from_local_datetime(&date.and_time(t)).single()
+        };
+
+        let midnight = at(today.succ_opt()?, NaiveTime::MIN);
+
+        match (from, to) {
+            (None, None) => Some(midnight),
+            (Some(f), None) if time >= f => Some(midnight),
+            (None, Some(t)) if time < t => Some(at(today, t)),
+            (Some(f), Some(t)) if f <= t && f <= time && time < t => Some(at(today, t)),
+            // Range spanning midnight.
+            (Some(f), Some(t)) if f > t && time >= f => Some(at(today.succ_opt()?, t)),
+            (Some(f), Some(t)) if f > t && time < t => Some(at(today, t)),
+            _ => None,
+        }
+    }
+
+    /// Days of week the rule applies to, every day if empty.
+    pub fn days(&self) -> Result<Vec<Weekday>, String> {
+        let mut days = Vec::<Weekday>::new();
+        for d in self.days.iter().flatten() {
+            match d.split_once('-') {
+                Some((first, last)) => {
+                    let (mut day, last) = (parse_day(first)?, parse_day(last)?);
+                    days.push(day);
+                    while day != last {
+                        day = day.succ();
+                        days.push(day);
+                    }
+                },
+                None => days.push(parse_day(d)?),
+            }
+        }
+        Ok(days)
+    }
+}
+
+fn parse_day(day: &str) -> Result<Weekday, String> {
+    Weekday::from_str(day.trim()).map_err(|_| format!("invalid day of week: {}", day))
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| format!("invalid time (HH:MM): {}", time))
+}
@@ -0,0 +1,207 @@
+use std::fs;
+use std::time::{Duration, Instant};
+
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+use super::cache::StatusCache;
+use super::calendar::Meeting;
+use super::config::StatusConfig;
+use super::rules::Signals;
+use super::BoxResult;
+
+/// Default time a script may run, in milliseconds.
+const TIMEOUT_MS: u64 = 200;
+
+/// Status script, for logic too odd for rules:
+///
+/// * path: Path of a [Rhai](https://rhai.rs) script, run at each update.
+/// * timeout_ms: Time the script may run, in milliseconds (200 by default).
+///
+/// The script reads the context from the `ctx` constant, see `Context`, and
+/// returns either a status, such as `#{ text: "lunch", emoji:
+/// ":fork_and_knife:", expire_after_hours: 1 }` (or with an `expiration`
+/// timestamp), or `()` to keep the built-in status. Scripts cannot read files
+/// nor import modules, and the built-in status is kept when they fail or run
+/// too long.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Script {
+    pub path: String,
+    pub timeout_ms: Option<u64>,
+}
+
+/// What the script is given, as the `ctx` map:
+///
+/// * `ips`: public IP addresses, as strings.
+/// * `location`: detected location name, `unknown` when no location is found
+///   or `vpn` while a VPN is up.
+/// * `ssid`, `city`, `country`, `country_code`: Wi-Fi network name and where
+///   the public IP is, `()` if unknown.
+/// * `vpn`, `ignored`: whether a VPN is up, whether the public IP is ignored.
+/// * `time`, `date`, `day`: local time (`HH:MM`), date (`YYYY-MM-DD`) and day
+///   of week (`mon` to `sun`), in the schedule timezone.
+/// * `timestamp`: current timestamp.
+/// * `cached`: status last set, `()` if unknown.
+/// * `meetings`: meetings going on or starting in the next 24 hours, with
+///   their `summary` and `start` and `end` timestamps.
+/// * `status`: built-in status, as computed without the script.
+///
+/// Statuses are maps of `text`, `emoji` and `expiration` timestamp (`()` if
+/// it does not expire).
+#[derive(Clone)]
+pub struct Context {
+    pub signals: Signals,
+    pub cached: Option<StatusCache>,
+    pub meetings: Vec<Meeting>,
+    pub status: StatusConfig,
+}
+
+impl Script {
+    /// Run the script, returning the status to set or `None` to keep the
+    /// built-in one.
+    pub fn run(&self, context: &Context) -> BoxResult<Option<StatusConfig>> {
+        let timeout = Duration::from_millis(self.timeout_ms.unwrap_or(TIMEOUT_MS));
+        let engine = engine(timeout);
+        let ast = self.compile(&engine)?;
+
+        let mut scope = Scope::new();
+        scope.push_constant("ctx", context.to_map());
+        let value = match engine.eval_ast_with_scope::<Dynamic>(&mut scope, &ast) {
+            Ok(v) => v,
+            Err(e) => bail!("Script {} failed: {}", self.path, e),
+        };
+        debug!("Script {} returned {:?}", self.path, value);
+
+        if value.is_unit() {
+            return Ok(None);
+        }
+        if !value.is::<Map>() {
+            bail!("Script {} must return a status map or (), not a {}", self.path, value.type_name());
+        }
+        match status(&value.cast::<Map>()) {
+            Ok(status) => Ok(Some(status)),
+            Err(e) => bail!("Script {} returned {}", self.path, e),
+        }
+    }
+
+    /// Describe what is wrong with the script, if anything.
+    pub fn check(&self) -> Result<(), String> {
+        if self.timeout_ms == Some(0) {
+            return Err("script timeout_ms must be positive".to_string());
+        }
+        self.compile(&engine(Duration::from_millis(TIMEOUT_MS))).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    fn compile(&self, engine: &Engine) -> BoxResult<AST> {
+        let source = match fs::read_to_string(&self.path) {
+            Ok(s) => s,
+            Err(e) => bail!("Cannot read script {}: {}", self.path, e),
+        };
+        match engine.compile(&source) {
+            Ok(ast) => Ok(ast),
+            Err(e) => bail!("Invalid script {}: {}", self.path, e),
+        }
+    }
+}
+
+/// Sandboxed engine: no modules, no `eval`, bounded sizes and running time.
+fn engine(timeout: Duration) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(64 * 1024);
+    engine.set_max_array_size(10_000);
+    engine.set_max_map_size(10_000);
+    engine.on_print(|s| debug!("Script: {}", s));
+    engine.on_debug(|s, _, pos| debug!("Script {}: {}", pos, s));
+
+    let start = Instant::now();
+    engine.on_progress(move |_| match start.elapsed() > timeout {
+        true => Some(format!("timed out after {} ms", timeout.as_millis()).into()),
+        false => None,
+    });
+    engine
+}
+
+/// Status returned by the script.
+fn status(map: &Map) -> Result<StatusConfig, String> {
+    let string = |key: &str| match map.get(key) {
+        None => Ok(None),
+        Some(v) => v.clone().into_string().map(Some).map_err(|t| format!("a {} {}, expected a string", t, key)),
+    };
+    let int = |key: &str| match map.get(key) {
+        None => Ok(None),
+        Some(v) if v.is_unit() => Ok(None),
+        Some(v) => v.as_int().map(Some).map_err(|t| format!("a {} {}, expected an integer", t, key)),
+    };
+
+    let (text, emoji) = (string("text")?, string("emoji")?);
+    if text.is_none() && emoji.is_none() {
+        return Err("a status without text nor emoji".to_string());
+    }
+    Ok(StatusConfig {
+        text: text.unwrap_or_default(),
+        emoji: emoji.unwrap_or_default(),
+        expire_after_hours: int("expire_after_hours")?,
+        expiration: int("expiration")?,
+    })
+}
+
+/// Status as a script map.
+fn status_map(text: &str, emoji: &str, expiration: Option<i64>) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("text".into(), text.into());
+    map.insert("emoji".into(), emoji.into());
+    map.insert("expiration".into(), expiration.map_or(Dynamic::UNIT, Dynamic::from));
+    map.into()
+}
+
+fn optional(value: Option<&String>) -> Dynamic {
+    value.map_or(Dynamic::UNIT, |v| v.as_str().into())
+}
+
+impl Context {
+    /// The `ctx` map.
+    fn to_map(&self) -> Map {
+        let signals = &self.signals;
+        let place = signals.place.as_ref();
+        let now = signals.now;
+
+        let mut map = Map::new();
+        let ips: Array = signals.ips.iter().map(|ip| ip.to_string().into()).collect();
+        map.insert("ips".into(), ips.into());
+        map.insert("location".into(), signals.location.as_str().into());
+        map.insert("ssid".into(), optional(signals.ssid.as_ref()));
+        map.insert("city".into(), optional(place.and_then(|p| p.city.as_ref())));
+        map.insert("country".into(), optional(place.and_then(|p| p.country.as_ref())));
+        map.insert("country_code".into(), optional(place.and_then(|p| p.country_code.as_ref())));
+        map.insert("vpn".into(), signals.vpn.into());
+        map.insert("ignored".into(), signals.ignored.into());
+        map.insert("time".into(), now.format("%H:%M").to_string().into());
+        map.insert("date".into(), now.format("%Y-%m-%d").to_string().into());
+        map.insert("day".into(), now.format("%a").to_string().to_lowercase().into());
+        map.insert("timestamp".into(), now.timestamp().into());
+
+        let cached = self.cached.as_ref().map_or(Dynamic::UNIT, |c| {
+            // Statuses without expiration are cached with 0.
+            status_map(&c.text, &c.emoji, Some(c.expiration).filter(|e| *e > 0))
+        });
+        map.insert("cached".into(), cached);
+
+        let meetings: Array = self.meetings.iter().map(|m| {
+            let mut meeting = Map::new();
+            meeting.insert("summary".into(), m.summary.as_str().into());
+            meeting.insert("start".into(), m.start.timestamp().into());
+            meeting.insert("end".into(), m.end.timestamp().into());
+            meeting.into()
+        }).collect();
+        map.insert("meetings".into(), meetings.into());
+
+        let status = &self.status;
+        let expiration = status.expiration_at(now.timestamp());
+        map.insert("status".into(), status_map(&status.text, &status.emoji, expiration));
+        map
+    }
+}
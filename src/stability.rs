@@ -0,0 +1,55 @@
+use super::cache::PendingLocation;
+
+/// How long a new location must be detected before status is switched to it,
+/// so that flaky networks do not flip status back and forth:
+///
+/// * detections: number of consecutive detections.
+/// * minutes: time since the location is detected.
+///
+/// When both are set, the first one reached wins. The unknown location is a
+/// location like any other.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Stability {
+    pub detections: Option<u32>,
+    pub minutes: Option<i64>,
+}
+
+impl Stability {
+    /// Record that `candidate` has been detected at `now` (a timestamp), while
+    /// status is set for the `current` location, and tell whether status can
+    /// be switched to the candidate.
+    ///
+    /// The candidate is kept in `pending` until it is stable.
+    pub fn settle(&self, current: Option<&str>, pending: &mut Option<PendingLocation>, candidate: &str, now: i64) -> bool {
+        // Nothing to switch, or nothing known to switch from.
+        if current.is_none_or(|c| c == candidate) {
+            *pending = None;
+            return true;
+        }
+
+        let p = match pending {
+            Some(p) if p.location == candidate => {
+                p.detections += 1;
+                p
+            },
+            _ => pending.insert(PendingLocation {
+                location: candidate.to_string(),
+                since: now,
+                detections: 1,
+            }),
+        };
+
+        let stable = match (self.detections, self.minutes) {
+            (None, None) => true,
+            (d, m) => d.is_some_and(|d| p.detections >= d)
+                || m.is_some_and(|m| now - p.since >= m * 60),
+        };
+
+        if stable {
+            *pending = None;
+        } else {
+            debug!("{} detected {} times since {}, not stable yet", p.location, p.detections, p.since);
+        }
+        stable
+    }
+}
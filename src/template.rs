@@ -0,0 +1,188 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::prelude::*;
+use chrono_tz::Tz;
+
+use super::geoip::Place;
+
+/// Variables of status templates.
+pub const VARIABLES: [&str; 9] = [
+    "location", "city", "country", "country_code", "flag", "local_time", "tz", "end_of_day", "expiration",
+];
+
+/// Variables holding a time, they accept a format.
+const TIMES: [&str; 3] = ["local_time", "end_of_day", "expiration"];
+
+/// Default format of times.
+const TIME_FORMAT: &str = "%H:%M";
+
+/// Status text or emoji with variables, such as `In {location} ({local_time}
+/// {tz})` or `Back at {expiration:%a %H:%M}`, see `Variables`. Braces are
+/// written `{{` and `}}`.
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Text(String),
+    /// Variable name, and its format for times.
+    Variable(String, Option<String>),
+}
+
+/// Values of template variables:
+///
+/// * `{location}`: detected location name, `unknown` when no location is found
+///   or `vpn` while a VPN is up.
+/// * `{city}`: city of the public IP found in the GeoIP database, or its
+///   country if the city is unknown.
+/// * `{country}`: country name, from the GeoIP database.
+/// * `{country_code}`: country code, such as `FR`.
+/// * `{flag}`: country flag emoji, such as `:flag-fr:`.
+/// * `{local_time}`: current time.
+/// * `{tz}`: timezone abbreviation, such as `CEST`.
+/// * `{end_of_day}`: end of the working day, see `Schedule`.
+/// * `{expiration}`: when the status expires, empty if it does not.
+///
+/// Times are in the schedule timezone, written `HH:MM` unless a strftime
+/// format is given, such as `{end_of_day:%Hh}`. Unknown values are empty.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Variables {
+    pub location: String,
+    pub place: Option<Place>,
+    pub now: DateTime<FixedOffset>,
+    pub tz: String,
+    pub end_of_day: Option<DateTime<FixedOffset>>,
+    pub expiration: Option<DateTime<FixedOffset>>,
+}
+
+impl Template {
+    /// Parse a template, unknown variables and invalid formats are errors.
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => return Err(format!("unclosed variable {{{}", spec)),
+                        }
+                    }
+
+                    let (name, format) = match spec.split_once(':') {
+                        Some((n, f)) => (n, Some(f.to_string())),
+                        None => (spec.as_str(), None),
+                    };
+                    check(name, format.as_deref())?;
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Variable(name.to_string(), format));
+                },
+                '}' => return Err("unmatched }, write }} for a brace".to_string()),
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Replace variables with their values.
+    pub fn render(&self, variables: &Variables) -> String {
+        self.parts.iter().map(|p| match p {
+            Part::Text(t) => t.clone(),
+            Part::Variable(name, format) =>
+                variables.get(name, format.as_deref().unwrap_or(TIME_FORMAT)),
+        }).collect()
+    }
+}
+
+/// Check a variable and its format.
+fn check(name: &str, format: Option<&str>) -> Result<(), String> {
+    if !VARIABLES.contains(&name) {
+        return Err(format!("unknown variable {{{}}}, expected one of: {}", name, VARIABLES.join(", ")));
+    }
+    match format {
+        Some(_) if !TIMES.contains(&name) => Err(format!("{{{}}} is not a time, it has no format", name)),
+        Some(f) if f.is_empty() || StrftimeItems::new(f).any(|i| matches!(i, Item::Error)) =>
+            Err(format!("invalid time format {:?} for {{{}}}", f, name)),
+        _ => Ok(()),
+    }
+}
+
+/// Replace variables in a template, invalid templates are kept as is.
+pub fn render(template: &str, variables: &Variables) -> String {
+    match Template::parse(template) {
+        Ok(t) => t.render(variables),
+        Err(e) => {
+            warn!("Invalid template \"{}\": {}", template, e);
+            template.to_string()
+        },
+    }
+}
+
+/// Time in the timezone, system timezone if not set.
+pub fn in_timezone(time: DateTime<Utc>, timezone: Option<Tz>) -> DateTime<FixedOffset> {
+    match timezone {
+        Some(tz) => time.with_timezone(&tz).fixed_offset(),
+        None => time.with_timezone(&Local).fixed_offset(),
+    }
+}
+
+/// Template of a text without variables.
+pub fn escape(text: &str) -> String {
+    text.replace('{', "{{").replace('}', "}}")
+}
+
+impl Variables {
+    /// Variables at this time, in the timezone (system timezone if not set).
+    pub fn at(now: DateTime<Utc>, timezone: Option<Tz>) -> Variables {
+        let tz = match timezone {
+            Some(tz) => now.with_timezone(&tz).format("%Z").to_string(),
+            None => now.with_timezone(&Local).format("%Z").to_string(),
+        };
+
+        Variables {
+            location: String::new(),
+            place: None,
+            now: in_timezone(now, timezone),
+            tz,
+            end_of_day: None,
+            expiration: None,
+        }
+    }
+
+    fn get(&self, name: &str, format: &str) -> String {
+        let time = |t: &DateTime<FixedOffset>| t.format(format).to_string();
+        let place = self.place.as_ref();
+        let country = place.and_then(|p| p.country.clone());
+
+        match name {
+            "location" => self.location.clone(),
+            "city" => place.and_then(|p| p.city.clone()).or(country).unwrap_or_default(),
+            "country" => country.unwrap_or_default(),
+            "country_code" => place.and_then(|p| p.country_code.clone()).unwrap_or_default(),
+            "flag" => place.and_then(|p| p.flag()).unwrap_or_default(),
+            "local_time" => time(&self.now),
+            "tz" => self.tz.clone(),
+            "end_of_day" => self.end_of_day.as_ref().map_or(String::new(), time),
+            "expiration" => self.expiration.as_ref().map_or(String::new(), time),
+            _ => String::new(),
+        }
+    }
+}
@@ -5,6 +5,244 @@ mod tests {
 
     use slack_status::*;
 
+    /// Serve the same HTTP response body forever on a local port, returns its
+    /// URL.
+    fn serve(body: &'static str) -> String {
+        serve_on("127.0.0.1:0", body)
+    }
+
+    /// Serve this body like `serve` on the IPv6 loopback, `None` when the host
+    /// has no IPv6.
+    fn serve_ipv6(body: &'static str) -> Option<String> {
+        std::net::TcpListener::bind("[::1]:0").ok()?;
+        Some(serve_on("[::1]:0", body))
+    }
+
+    /// Answer every HTTP request with this body on a local address, returns its
+    /// URL.
+    fn serve_on(address: &str, body: &'static str) -> String {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind(address).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request);
+                let _ = write!(stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(), body);
+            }
+        });
+        url
+    }
+
+    /// Answer DNS queries forever on a local port with an A record and a TXT
+    /// record, returns its address.
+    fn serve_dns() -> String {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let mut query = [0; 512];
+            loop {
+                let (len, peer) = socket.recv_from(&mut query).unwrap();
+                let mut response = query[..len].to_vec();
+                response[2] = 0x81;
+                response[3] = 0x80;
+                response[7] = 2;
+                // Answers, pointing to the question name.
+                response.extend_from_slice(&[0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 0x0c]);
+                if query[len - 3] == 16 {
+                    response.extend_from_slice(&[0xc0, 0x0c, 0, 16, 0, 1, 0, 0, 0, 60, 0, 25, 11]);
+                    response.extend_from_slice(b"edns0-hello");
+                    response.push(12);
+                    response.extend_from_slice(b"198.51.100.1");
+                } else {
+                    response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 203, 0, 113, 17]);
+                }
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        address
+    }
+
+    /// Answer STUN binding requests forever on a local port with the address
+    /// they come from, after ignoring the first one. Returns its address.
+    fn serve_stun() -> String {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let mut request = [0; 512];
+            let _ = socket.recv_from(&mut request).unwrap();
+            loop {
+                let (_, peer) = socket.recv_from(&mut request).unwrap();
+                let mut response = vec![0x01, 0x01, 0, 12];
+                response.extend_from_slice(&request[4..20]);
+                response.extend_from_slice(&[0, 0x20, 0, 8, 0, 1]);
+                response.extend_from_slice(&(peer.port() ^ 0x2112).to_be_bytes());
+                match peer.ip() {
+                    IpAddr::V4(ip) => {
+                        let xored = u32::from(ip) ^ 0x2112_a442;
+                        response.extend_from_slice(&xored.to_be_bytes());
+                    },
+                    IpAddr::V6(_) => unreachable!(),
+                }
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        address
+    }
+
+    /// Answer gpsd watch requests with a report without fix then a report
+    /// with a fix at this position, returns its address.
+    fn serve_gpsd(latitude: f64, longitude: f64) -> String {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut watch = String::new();
+                let _ = BufReader::new(&stream).read_line(&mut watch);
+                assert!(watch.starts_with("?WATCH="));
+                let _ = write!(stream, concat!(
+                    "{{\"class\":\"VERSION\",\"release\":\"3.22\",\"proto_major\":3,\"proto_minor\":14}}\n",
+                    "{{\"class\":\"DEVICES\",\"devices\":[{{\"class\":\"DEVICE\",\"path\":\"/dev/ttyACM0\"}}]}}\n",
+                    "{{\"class\":\"WATCH\",\"enable\":true,\"json\":true}}\n",
+                    "{{\"class\":\"TPV\",\"device\":\"/dev/ttyACM0\",\"mode\":1}}\n",
+                    "{{\"class\":\"TPV\",\"device\":\"/dev/ttyACM0\",\"mode\":3,\"lat\":{},\"lon\":{},\"alt\":35.0}}\n"),
+                    latitude, longitude);
+            }
+        });
+        address
+    }
+
+    /// Write a MaxMind DB (IPv4 only) mapping networks to records, returns its
+    /// path.
+    fn write_geoip(name: &str, networks: &[(&str, serde_json::Value)]) -> std::path::PathBuf {
+        use serde_json::{json, Value};
+
+        fn encode(value: &Value, out: &mut Vec<u8>) {
+            let (kind, bytes) = match value {
+                Value::String(s) => (2, s.as_bytes().to_vec()),
+                Value::Number(n) => {
+                    let n = n.as_u64().unwrap().to_be_bytes();
+                    (6, n.iter().skip_while(|b| **b == 0).cloned().collect())
+                },
+                Value::Object(m) => {
+                    out.push(7 << 5 | m.len() as u8);
+                    for (k, v) in m {
+                        encode(&json!(k), out);
+                        encode(v, out);
+                    }
+                    return;
+                },
+                Value::Array(a) => {
+                    out.extend(&[a.len() as u8, 11 - 7]);
+                    a.iter().for_each(|v| encode(v, out));
+                    return;
+                },
+                other => panic!("Cannot encode {} in a test GeoIP database", other),
+            };
+            out.push(kind << 5 | bytes.len() as u8);
+            out.extend(bytes);
+        }
+
+        // Search tree, each node has two records: a node index, a data offset
+        // or nothing (None).
+        let mut nodes: Vec<[Option<usize>; 2]> = vec![[None, None]];
+        let mut data = Vec::<u8>::new();
+        let mut leaves = Vec::<(usize, usize, usize)>::new();
+        for (network, record) in networks {
+            let network = Network::from_str(network).unwrap();
+            let bits = match network.addr() {
+                IpAddr::V4(ip) => u32::from(ip),
+                IpAddr::V6(_) => panic!("Cannot map IPv6 network {} in a test GeoIP database", network),
+            };
+            let mut node = 0;
+            for i in 0..network.prefix_len() as usize - 1 {
+                let bit = (bits >> (31 - i) & 1) as usize;
+                node = match nodes[node][bit] {
+                    Some(n) => n,
+                    None => {
+                        nodes.push([None, None]);
+                        nodes[node][bit] = Some(nodes.len() - 1);
+                        nodes.len() - 1
+                    },
+                };
+            }
+            let bit = (bits >> (32 - network.prefix_len() as u32) & 1) as usize;
+            leaves.push((node, bit, data.len()));
+            encode(record, &mut data);
+        }
+
+        let count = nodes.len();
+        let mut records: Vec<[usize; 2]> = nodes.iter()
+            .map(|n| [n[0].unwrap_or(count), n[1].unwrap_or(count)])
+            .collect();
+        for (node, bit, offset) in leaves {
+            records[node][bit] = count + 16 + offset;
+        }
+
+        let mut db = Vec::<u8>::new();
+        for r in records.iter().flatten() {
+            db.extend(&(*r as u32).to_be_bytes()[1..]);
+        }
+        db.extend(&[0; 16]);
+        db.extend(data);
+        db.extend(b"\xAB\xCD\xEFMaxMind.com");
+        encode(&json!({
+            "binary_format_major_version": 2,
+            "binary_format_minor_version": 0,
+            "build_epoch": 1_700_000_000,
+            "database_type": "GeoIP2-City",
+            "description": {"en": "Test"},
+            "ip_version": 4,
+            "languages": ["en"],
+            "node_count": count,
+            "record_size": 24,
+        }), &mut db);
+
+        let path = std::env::temp_dir().join(format!("slack-status-{}-{}.mmdb", name, std::process::id()));
+        std::fs::write(&path, db).unwrap();
+        path
+    }
+
+    /// URL of a local port nobody listens to.
+    fn unreachable() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    /// Detector returning a fixed answer.
+    struct FakeDetector {
+        name: &'static str,
+        result: Result<Option<usize>, &'static str>,
+    }
+
+    impl LocationDetector for FakeDetector {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn detect(&self, config: &Config, detection: &mut Detection) -> BoxResult<bool> {
+            match self.result {
+                Ok(Some(i)) => {
+                    detection.location = Some(config.locations[i].clone());
+                    Ok(true)
+                },
+                Ok(None) => Ok(false),
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+
+    fn fake(name: &'static str, result: Result<Option<usize>, &'static str>) -> Box<dyn LocationDetector> {
+        Box::new(FakeDetector { name, result })
+    }
+
     #[test]
     fn test_status_from_location_0() {
         let config = Config::with("xxx".to_string());
@@ -19,10 +257,11 @@ mod tests {
         let mut config = Config::with("xxx".to_string());
         config.locations = vec![
             Location {
-                ip: IpAddr::from_str("123.45.67.89").unwrap(),
+                ip: Some(IpAddr::from_str("123.45.67.89").unwrap().into()),
                 text: String::from_str("here!").unwrap(),
                 emoji: String::from_str(":yolo:").unwrap(),
                 expire_after_hours: Some(1),
+                ..Default::default()
             },
         ];
         let client = SlackStatus::from(&config).unwrap();
@@ -36,16 +275,18 @@ mod tests {
         let mut config = Config::with("xxx".to_string());
         config.locations = vec![
             Location {
-                ip: IpAddr::from_str("123.45.67.89").unwrap(),
+                ip: Some(IpAddr::from_str("123.45.67.89").unwrap().into()),
                 text: String::from_str("here!").unwrap(),
                 emoji: String::from_str(":yolo:").unwrap(),
                 expire_after_hours: Some(1),
+                ..Default::default()
             },
             Location {
-                ip: IpAddr::from_str("98.76.54.32").unwrap(),
+                ip: Some(IpAddr::from_str("98.76.54.32").unwrap().into()),
                 text: String::from_str("there!").unwrap(),
                 emoji: String::from_str(":yolo:").unwrap(),
                 expire_after_hours: Some(1),
+                ..Default::default()
             },
         ];
         let client = SlackStatus::from(&config).unwrap();
@@ -53,4 +294,1748 @@ mod tests {
 
         assert_eq!(status.unwrap().text, "here!");
     }
+
+    #[test]
+    fn test_status_from_location_network() {
+        let mut config = Config::with("xxx".to_string());
+        config.locations = vec![
+            Location {
+                ip: Some(Network::from_str("203.0.113.16/28").unwrap()),
+                text: String::from_str("office").unwrap(),
+                emoji: String::from_str(":office:").unwrap(),
+                ..Default::default()
+            },
+            Location {
+                ip: Some(Network::from_str("2001:db8:1::/64").unwrap()),
+                text: String::from_str("office v6").unwrap(),
+                emoji: String::from_str(":office:").unwrap(),
+                ..Default::default()
+            },
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+
+        let status = client.status_from_location(&IpAddr::from_str("203.0.113.30").unwrap());
+        assert_eq!(status.unwrap().text, "office");
+        let status = client.status_from_location(&IpAddr::from_str("2001:db8:1::abcd:1234").unwrap());
+        assert_eq!(status.unwrap().text, "office v6");
+        let status = client.status_from_location(&IpAddr::from_str("203.0.113.32").unwrap());
+        assert!(status.is_none());
+    }
+
+    #[test]
+    fn test_network_from_str() {
+        let host = Network::from_str("123.45.67.89").unwrap();
+        assert!(host.is_host());
+        assert_eq!(host.to_string(), "123.45.67.89");
+
+        let network = Network::from_str("203.0.113.17/28").unwrap();
+        assert_eq!(network.to_string(), "203.0.113.16/28");
+
+        assert!(Network::from_str("203.0.113.0/33").is_err());
+        assert!(Network::from_str("office").is_err());
+    }
+
+    #[test]
+    fn test_config_single_ip_and_networks() {
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            ignore_ips = ["198.51.100.1", "2001:db8:ffff::/48"]
+
+            [[locations]]
+            ip = "123.45.67.89"
+            text = "here!"
+            emoji = ":yolo:"
+
+            [[locations]]
+            ip = "203.0.113.16/28"
+            text = "office"
+            emoji = ":office:"
+        "#).unwrap();
+
+        assert_eq!(config.detectors, vec!["public_ip"]);
+        assert_eq!(config.locations[0].ip, Some(Network::from_str("123.45.67.89").unwrap()));
+        assert_eq!(config.locations[1].ip.unwrap().prefix_len(), 28);
+        assert!(config.ignore_ips[1].contains(&IpAddr::from_str("2001:db8:ffff:1::1").unwrap()));
+
+        let saved = toml::to_string(&config).unwrap();
+        assert!(saved.contains("ip = \"123.45.67.89\"\n"));
+        assert!(saved.contains("ip = \"203.0.113.16/28\"\n"));
+    }
+
+    fn location(name: &str, ip: &str, priority: Option<i64>) -> Location {
+        Location {
+            name: Some(name.to_string()),
+            ip: Some(Network::from_str(ip).unwrap()),
+            priority,
+            text: name.to_string(),
+            emoji: String::from_str(":yolo:").unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_status_from_location_most_specific() {
+        let mut config = Config::with("xxx".to_string());
+        config.locations = vec![
+            location("campus", "203.0.113.0/24", None),
+            location("office", "203.0.113.16/28", None),
+            location("lab", "203.0.113.17", None),
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+
+        let status = client.status_from_location(&IpAddr::from_str("203.0.113.17").unwrap());
+        assert_eq!(status.unwrap().text, "lab");
+        let status = client.status_from_location(&IpAddr::from_str("203.0.113.18").unwrap());
+        assert_eq!(status.unwrap().text, "office");
+        let status = client.status_from_location(&IpAddr::from_str("203.0.113.1").unwrap());
+        assert_eq!(status.unwrap().text, "campus");
+        assert!(config.check().is_ok());
+    }
+
+    #[test]
+    fn test_status_from_location_priority() {
+        let mut config = Config::with("xxx".to_string());
+        config.locations = vec![
+            location("office", "203.0.113.16/28", None),
+            location("campus", "203.0.113.0/24", Some(1)),
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+        let status = client.status_from_location(&IpAddr::from_str("203.0.113.17").unwrap());
+
+        assert_eq!(status.unwrap().text, "campus");
+    }
+
+    #[test]
+    fn test_config_check_conflicts() {
+        let mut config = Config::with("xxx".to_string());
+        config.locations = vec![
+            location("office", "203.0.113.16/28", None),
+            location("meeting room", "203.0.113.16/28", Some(0)),
+            location("lab", "203.0.113.16/28", Some(1)),
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+        let status = client.status_from_location(&IpAddr::from_str("203.0.113.17").unwrap());
+        assert_eq!(status.unwrap().text, "lab");
+
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("\"office\" and \"meeting room\" both match 203.0.113.16/28"));
+        assert!(!error.contains("lab"));
+    }
+
+    #[test]
+    fn test_detect_first_confident_wins() {
+        let mut config = Config::with("xxx".to_string());
+        config.locations = vec![
+            location("office", "203.0.113.16/28", None),
+            location("home", "198.51.100.7", None),
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+
+        let detection = client.detect_with(&[
+            fake("broken", Err("no network")),
+            fake("unsure", Ok(None)),
+            fake("home", Ok(Some(1))),
+            fake("office", Ok(Some(0))),
+        ]).unwrap();
+        assert_eq!(detection.detector.as_deref(), Some("home"));
+        assert_eq!(client.status_from(&detection).text, "home");
+
+        let detection = client.detect_with(&[
+            fake("broken", Err("no network")),
+            fake("unsure", Ok(None)),
+        ]).unwrap();
+        assert!(detection.location.is_none());
+        assert_eq!(client.status_from(&detection).text, "commuting");
+
+        assert!(client.detect_with(&[fake("broken", Err("no network"))]).is_err());
+    }
+
+    #[test]
+    fn test_config_check_unknown_detector() {
+        let mut config = Config::with("xxx".to_string());
+        config.detectors = vec!["public_ip".to_string(), "telepathy".to_string()];
+
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("\"telepathy\""));
+    }
+
+    #[test]
+    fn test_ssid_parsing() {
+        use slack_status::detector::ssid::*;
+
+        let nmcli = "no:Neighbours\nyes:Corp\\:WiFi\nno:\n";
+        assert_eq!(parse_nmcli(nmcli).unwrap(), "Corp:WiFi");
+        assert!(parse_nmcli("no:Corp-WiFi\n").is_none());
+
+        let iw = "Connected to 00:11:22:33:44:55 (on wlp2s0)\n\tSSID: Corp-WiFi\n\tfreq: 5180\n";
+        assert_eq!(parse_iw_link(iw).unwrap(), "Corp-WiFi");
+        assert!(parse_iw_link("Not connected.\n").is_none());
+
+        let interfaces = wireless_interfaces(std::path::Path::new("tests/fixtures/proc")).unwrap();
+        assert_eq!(interfaces, vec!["wlp2s0"]);
+        let interfaces = wireless_interfaces(std::path::Path::new("tests/fixtures/nowhere")).unwrap();
+        assert!(interfaces.is_empty());
+    }
+
+    #[test]
+    fn test_config_ssid_locations() {
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            detectors = ["ssid", "public_ip"]
+            ignore_ips = []
+
+            [[locations]]
+            name = "office"
+            ssid = "Corp-WiFi"
+            text = "at the office"
+            emoji = ":office:"
+
+            [[locations]]
+            name = "office guests"
+            ssid = "Corp-WiFi"
+            ip = "203.0.113.16/28"
+            text = "visiting"
+            emoji = ":office:"
+
+            [[locations]]
+            name = "nowhere"
+            text = "lost"
+            emoji = ":question:"
+        "#).unwrap();
+
+        let office = slack_status::location::best_match(&config.locations,
+            |l| l.ssid.as_deref() == Some("Corp-WiFi"));
+        assert_eq!(office.unwrap().label(), "office guests");
+
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("\"nowhere\" has no ip, ip6, ssid, gateway_mac, local_network nor geofence"));
+        assert!(!error.contains("\"office\" and"));
+    }
+
+    #[test]
+    fn test_gateway_mac_detector() {
+        use std::net::Ipv4Addr;
+        use slack_status::detector::gateway_mac::*;
+
+        let route = std::fs::read_to_string("tests/fixtures/proc/net/route").unwrap();
+        let (interface, gateway) = default_gateway(&route).unwrap();
+        assert_eq!(interface, "enp0s31f6");
+        assert_eq!(gateway, Ipv4Addr::new(192, 168, 0, 1));
+
+        let arp = std::fs::read_to_string("tests/fixtures/proc/net/arp").unwrap();
+        assert!(neighbour_mac(&arp, &Ipv4Addr::new(192, 168, 0, 7), "enp0s31f6").is_none());
+        assert!(neighbour_mac(&arp, &Ipv4Addr::new(192, 168, 0, 1), "wlp2s0").is_none());
+
+        let mut config = Config::with("xxx".to_string());
+        config.detectors = vec!["gateway_mac".to_string()];
+        config.proc_root = Some("tests/fixtures/proc".to_string());
+        config.locations = vec![
+            Location {
+                name: Some("home".to_string()),
+                gateway_mac: Some("a0-b1-c2-d3-e4-f5".to_string()),
+                ..Default::default()
+            },
+            Location {
+                name: Some("office".to_string()),
+                gateway_mac: Some("00-11-22-AA-BB-CC".to_string()),
+                ..Default::default()
+            },
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+
+        let detection = client.detect().unwrap();
+        assert_eq!(detection.gateway_mac.as_deref(), Some("00:11:22:aa:bb:cc"));
+        assert_eq!(detection.location.unwrap().label(), "office");
+        assert!(detection.ips.is_empty());
+    }
+
+    #[test]
+    fn test_vpn_detector() {
+        use slack_status::detector::is_vpn_interface;
+
+        let patterns = vec!["tun*".to_string(), "wg?".to_string(), "ppp0".to_string()];
+        assert!(is_vpn_interface("tun0", &patterns));
+        assert!(is_vpn_interface("wg1", &patterns));
+        assert!(is_vpn_interface("ppp0", &patterns));
+        assert!(!is_vpn_interface("wg10", &patterns));
+        assert!(!is_vpn_interface("wlp2s0", &patterns));
+
+        assert!(detector::vpn::is_up(Some(0x91), Some("unknown")));
+        assert!(detector::vpn::is_up(None, None));
+        assert!(!detector::vpn::is_up(Some(0x1090), Some("unknown")));
+        assert!(!detector::vpn::is_up(Some(0x1091), Some("down\n")));
+
+        let mut config = Config::with("xxx".to_string());
+        config.detectors = vec!["vpn".to_string(), "gateway_mac".to_string()];
+        config.proc_root = Some("tests/fixtures/proc".to_string());
+        config.sys_root = Some("tests/fixtures/sys".to_string());
+        config.vpn_interfaces = vec!["tun*".to_string(), "wg*".to_string()];
+        config.locations = vec![Location {
+            name: Some("office".to_string()),
+            gateway_mac: Some("00:11:22:aa:bb:cc".to_string()),
+            ..Default::default()
+        }];
+
+        let client = SlackStatus::from(&config).unwrap();
+        let detection = client.detect().unwrap();
+        assert_eq!(detection.vpn.as_deref(), Some("wg0"));
+        assert!(detection.ignored);
+        assert!(detection.location.is_none());
+
+        config.vpn_status = Some(StatusConfig {
+            text: "on VPN".to_string(),
+            emoji: ":lock:".to_string(),
+            expire_after_hours: None,
+            ..Default::default()
+        });
+        let client = SlackStatus::from(&config).unwrap();
+        let detection = client.detect().unwrap();
+        assert!(!detection.ignored);
+        assert_eq!(client.status_from(&detection).text, "on VPN");
+
+        // tun0 is configured but down, the next detector is asked.
+        config.vpn_interfaces = vec!["tun*".to_string()];
+        let client = SlackStatus::from(&config).unwrap();
+        let detection = client.detect().unwrap();
+        assert!(detection.vpn.is_none());
+        assert_eq!(detection.location.unwrap().label(), "office");
+    }
+
+    #[test]
+    fn test_config_check_vpn() {
+        let mut config = Config::with("xxx".to_string());
+        config.vpn_interfaces = vec!["tun[".to_string()];
+        config.vpn_status = Some(StatusConfig {
+            text: "on VPN".to_string(),
+            emoji: ":lock:".to_string(),
+            expire_after_hours: None,
+            ..Default::default()
+        });
+
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("invalid vpn_interfaces pattern \"tun[\""));
+        assert!(error.contains("vpn_status is set but the vpn detector is not enabled"));
+    }
+
+    #[test]
+    fn test_geofence_distance() {
+        let eiffel_tower = Position { latitude: 48.8584, longitude: 2.2945 };
+        let notre_dame = Position { latitude: 48.8530, longitude: 2.3499 };
+        let distance = eiffel_tower.distance(&notre_dame);
+        assert!((distance - 4090.0).abs() < 20.0, "{}", distance);
+
+        let geofence = Geofence { latitude: 48.8584, longitude: 2.2945, radius: 200.0 };
+        assert!(geofence.contains(&Position { latitude: 48.8594, longitude: 2.2955 }));
+        assert!(!geofence.contains(&notre_dame));
+    }
+
+    #[test]
+    fn test_gps_detector() {
+        let mut config = Config::with("xxx".to_string());
+        config.detectors = vec!["gps".to_string()];
+        config.gpsd_address = Some(serve_gpsd(48.8590, 2.2950));
+        config.locations = vec![
+            Location {
+                name: Some("paris".to_string()),
+                geofence: Some(Geofence { latitude: 48.8566, longitude: 2.3522, radius: 10000.0 }),
+                ..Default::default()
+            },
+            Location {
+                name: Some("client".to_string()),
+                geofence: Some(Geofence { latitude: 48.8584, longitude: 2.2945, radius: 200.0 }),
+                ..Default::default()
+            },
+            Location {
+                name: Some("notre dame".to_string()),
+                geofence: Some(Geofence { latitude: 48.8530, longitude: 2.3499, radius: 200.0 }),
+                ..Default::default()
+            },
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+
+        // Both "paris" and "client" contain the position, "client" is nearer.
+        let detection = client.detect().unwrap();
+        assert_eq!(detection.position, Some(Position { latitude: 48.8590, longitude: 2.2950 }));
+        assert_eq!(detection.location.unwrap().label(), "client");
+
+        config.locations[0].priority = Some(1);
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.detect().unwrap().location.unwrap().label(), "paris");
+
+        config.locations[0].geofence = Some(Geofence { latitude: 91.0, longitude: 0.0, radius: 0.0 });
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("\"paris\" geofence: latitude 91 is not between -90 and 90"));
+    }
+
+    #[test]
+    fn test_geoip_unknown_location() {
+        use serde_json::json;
+
+        let path = write_geoip("unknown", &[
+            ("203.0.113.0/24", json!({
+                "city": {"names": {"en": "Paris", "fr": "Paris"}},
+                "country": {"iso_code": "FR", "names": {"en": "France"}},
+            })),
+            ("198.51.100.128/25", json!({
+                "country": {"iso_code": "DE", "names": {"en": "Germany"}},
+            })),
+        ]);
+
+        let geoip = GeoIp::open(&path).unwrap();
+        let place = geoip.lookup(&IpAddr::from_str("203.0.113.17").unwrap()).unwrap().unwrap();
+        assert_eq!(place.city.as_deref(), Some("Paris"));
+        assert_eq!(place.flag().as_deref(), Some(":flag-fr:"));
+        assert!(geoip.lookup(&IpAddr::from_str("198.51.100.17").unwrap()).unwrap().is_none());
+
+        let mut config = Config::with("xxx".to_string());
+        config.ip_request_address = vec![serve("198.51.100.200").into()];
+        config.geoip_database = Some(path.to_string_lossy().to_string());
+        let client = SlackStatus::from(&config).unwrap();
+
+        // The city is unknown, the country is used instead.
+        let detection = client.detect().unwrap();
+        assert!(detection.location.is_none());
+        let status = client.status_from(&detection);
+        assert_eq!(status.text, "working from Germany");
+        assert_eq!(status.emoji, ":flag-de:");
+
+        config.ip_request_address = vec![serve("203.0.113.17").into()];
+        config.geoip_status = Some(StatusConfig {
+            text: "{city}, {country} ({country_code})".to_string(),
+            emoji: ":earth_africa:".to_string(),
+            expire_after_hours: Some(2),
+            ..Default::default()
+        });
+        let client = SlackStatus::from(&config).unwrap();
+        let status = client.status_from(&client.detect().unwrap());
+        assert_eq!(status.text, "Paris, France (FR)");
+        assert_eq!(status.emoji, ":earth_africa:");
+
+        std::fs::remove_file(&path).unwrap();
+        config.geoip_database = Some(path.to_string_lossy().to_string());
+        assert!(config.check().unwrap_err().to_string().contains("Cannot open GeoIP database"));
+    }
+
+    #[test]
+    fn test_stability_detections() {
+        let stability = Stability { detections: Some(3), minutes: None };
+        let mut pending = None;
+
+        // Nothing known to switch from, or same location.
+        assert!(stability.settle(None, &mut pending, "hotel", 0));
+        assert!(stability.settle(Some("hotel"), &mut pending, "hotel", 600));
+
+        assert!(!stability.settle(Some("hotel"), &mut pending, "unknown", 1200));
+        assert!(!stability.settle(Some("hotel"), &mut pending, "unknown", 1800));
+        assert_eq!(pending, Some(PendingLocation { location: "unknown".to_string(), since: 1200, detections: 2 }));
+
+        // Back to the hotel before being stable, the candidate is dropped.
+        assert!(stability.settle(Some("hotel"), &mut pending, "hotel", 2400));
+        assert!(pending.is_none());
+
+        assert!(!stability.settle(Some("hotel"), &mut pending, "unknown", 3000));
+        assert!(!stability.settle(Some("hotel"), &mut pending, "office", 3600));
+        assert!(!stability.settle(Some("hotel"), &mut pending, "office", 4200));
+        assert!(stability.settle(Some("hotel"), &mut pending, "office", 4800));
+        assert!(pending.is_none());
+    }
+
+    #[test]
+    fn test_stability_minutes() {
+        let stability = Stability { detections: Some(10), minutes: Some(15) };
+        let mut pending = None;
+
+        assert!(!stability.settle(Some("office"), &mut pending, "unknown", 0));
+        assert!(!stability.settle(Some("office"), &mut pending, "unknown", 600));
+        assert!(stability.settle(Some("office"), &mut pending, "unknown", 900));
+
+        // Without any setting, switching is immediate.
+        assert!(Stability::default().settle(Some("office"), &mut pending, "home", 1000));
+    }
+
+    #[test]
+    fn test_grace_period() {
+        let mut config = Config::with("xxx".to_string());
+        assert!(!config.in_grace_period(Some(1000), 1000));
+
+        config.grace_period = Some(30);
+        assert!(!config.in_grace_period(None, 1000));
+        assert!(config.in_grace_period(Some(1000), 1000 + 30 * 60));
+        assert!(!config.in_grace_period(Some(1000), 1001 + 30 * 60));
+    }
+
+    #[test]
+    fn test_transitions() {
+        let mut config: Config = toml::from_str(r#"
+            token = "xxx"
+            ignore_ips = []
+
+            [[transitions]]
+            from = "office"
+            to = "home"
+            text = "home early"
+            emoji = ":tada:"
+
+            [[locations]]
+            name = "office"
+            ip = "203.0.113.16/28"
+            text = "at the office"
+            emoji = ":office:"
+            on_leave = { text = "on my way home", emoji = ":bike:", expire_after_hours = 1 }
+
+            [[locations]]
+            name = "home"
+            ip = "198.51.100.0/24"
+            text = "working remotely"
+            emoji = ":house_with_garden:"
+        "#).unwrap();
+        assert!(config.check().is_ok());
+
+        let client = SlackStatus::from(&config).unwrap();
+        let office = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+        let home = Detection { location: Some(config.locations[1].clone()), ..Default::default() };
+        let unknown = Detection::default();
+        let mut cache = Cache { location: Some("office".to_string()), ..Default::default() };
+
+        assert_eq!(client.transition_status(&cache, &unknown, 0).unwrap().text, "on my way home");
+        assert_eq!(client.transition_status(&cache, &home, 0).unwrap().text, "home early");
+        assert!(client.transition_status(&cache, &office, 0).is_none());
+        cache.location = Some("home".to_string());
+        assert!(client.transition_status(&cache, &office, 0).is_none());
+        assert!(client.transition_status(&cache, &unknown, 0).is_none());
+
+        // The transition status is kept until it expires.
+        cache.location = Some("unknown".to_string());
+        cache.transition = true;
+        cache.status = StatusCache { text: "on my way home".to_string(), emoji: ":bike:".to_string(), expiration: 3600 };
+        let kept = client.transition_status(&cache, &unknown, 1800).unwrap();
+        assert_eq!((kept.text.as_str(), kept.expiration), ("on my way home", Some(3600)));
+        assert!(client.transition_status(&cache, &unknown, 3600).is_none());
+
+        // Kept as set, even with braces.
+        cache.status.text = "on my way {home}".to_string();
+        let kept = client.transition_status(&cache, &unknown, 1800).unwrap();
+        assert_eq!(client.render(kept, &unknown, chrono::Utc::now()).text, "on my way {home}");
+
+        // Rules win over on_leave statuses.
+        config.transitions.push(Transition {
+            from: None,
+            to: Some("unknown".to_string()),
+            text: "commuting".to_string(),
+            emoji: ":train:".to_string(),
+            expire_after_hours: Some(1),
+        });
+        let client = SlackStatus::from(&config).unwrap();
+        cache = Cache { location: Some("office".to_string()), ..Default::default() };
+        assert_eq!(client.transition_status(&cache, &unknown, 0).unwrap().text, "commuting");
+
+        config.transitions[0].to = Some("hq".to_string());
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("transition from office to hq refers to unknown location \"hq\""));
+    }
+
+    /// Directory of its own for a test, holding an empty status cache, see
+    /// `cache_file`.
+    fn test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("slack-status-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Cache::default().save_to(&cache_file(&dir)).unwrap();
+        dir
+    }
+
+    /// Status cache file of a test directory, the user cache is left alone.
+    fn cache_file(dir: &std::path::Path) -> std::path::PathBuf {
+        dir.join("status.json")
+    }
+
+    /// Configuration with a home location, and a test directory.
+    fn cache_config(name: &str) -> (Config, std::path::PathBuf) {
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            ignore_ips = []
+
+            [[locations]]
+            name = "home"
+            ip = "198.51.100.0/24"
+            text = "working remotely"
+            emoji = ":house_with_garden:"
+        "#).unwrap();
+        (config, test_dir(name))
+    }
+
+    #[test]
+    fn test_cached_stability() {
+        let (mut config, dir) = cache_config("stability");
+        config.stability = Some(Stability { detections: Some(2), minutes: None });
+
+        let client = SlackStatus::with_cache_file(&config, cache_file(&dir)).unwrap();
+        let home = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+        let unknown = Detection::default();
+
+        // Nothing known yet.
+        assert!(client.is_stable(&home));
+        client.remember_location(&home, Source::Location);
+
+        assert!(!client.is_stable(&unknown));
+        assert_eq!(client.read_cache().unwrap().pending.unwrap().detections, 1);
+        assert!(client.is_stable(&unknown));
+        assert!(client.read_cache().unwrap().pending.is_none());
+
+        // Ignored detections are always stable and keep the cache as is.
+        client.remember_location(&unknown, Source::Location);
+        assert!(client.is_stable(&Detection { ignored: true, ..Default::default() }));
+        assert_eq!(client.read_cache().unwrap().location.as_deref(), Some("unknown"));
+
+        // Until the new location is stable, the previous one is kept.
+        client.remember_location(&home, Source::Location);
+        let mut detection = unknown.clone();
+        assert!(!client.stabilize(&mut detection));
+        assert_eq!(detection.location_name(), "home");
+        assert_eq!(client.status_from(&detection).text, "working remotely");
+        let mut detection = unknown.clone();
+        assert!(client.stabilize(&mut detection));
+        assert_eq!(detection.location_name(), "unknown");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cached_grace_period() {
+        let (mut config, dir) = cache_config("grace");
+        config.grace_period = Some(30);
+
+        let client = SlackStatus::with_cache_file(&config, cache_file(&dir)).unwrap();
+        assert!(!client.in_grace_period());
+
+        let mut home = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+        client.apply_grace_period(&mut home);
+        assert!(!home.ignored);
+        assert!(client.read_cache().unwrap().last_seen.is_some());
+        assert!(client.in_grace_period());
+
+        let mut unknown = Detection::default();
+        client.apply_grace_period(&mut unknown);
+        assert!(unknown.ignored);
+
+        // Seen too long ago.
+        let cache = Cache { last_seen: Some(0), ..Default::default() };
+        cache.save_to(&cache_file(&dir)).unwrap();
+        let mut unknown = Detection::default();
+        client.apply_grace_period(&mut unknown);
+        assert!(!unknown.ignored);
+        assert!(!client.in_grace_period());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cached_transitions() {
+        let mut config: Config = toml::from_str(r#"
+            token = "xxx"
+            ignore_ips = []
+
+            [[transitions]]
+            from = "office"
+            to = "home"
+            text = "home early"
+            emoji = ":tada:"
+
+            [[locations]]
+            name = "office"
+            ip = "203.0.113.16/28"
+            text = "at the office"
+            emoji = ":office:"
+            on_leave = { text = "on my way home", emoji = ":bike:", expire_after_hours = 1 }
+
+            [[locations]]
+            name = "home"
+            ip = "198.51.100.0/24"
+            text = "working remotely"
+            emoji = ":house_with_garden:"
+        "#).unwrap();
+        let dir = test_dir("transitions");
+
+        let client = SlackStatus::with_cache_file(&config, cache_file(&dir)).unwrap();
+        let office = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+        let home = Detection { location: Some(config.locations[1].clone()), ..Default::default() };
+        let unknown = Detection::default();
+        // Status is set, then the location remembered.
+        let run = |detection: &Detection, meetings: &[Meeting]| {
+            let (status, source) = client.status_with(detection, meetings, chrono::Utc::now());
+            client.remember_location(detection, source);
+            status.text
+        };
+
+        assert_eq!(run(&office, &[]), "at the office");
+        assert!(!client.read_cache().unwrap().transition);
+
+        assert_eq!(run(&unknown, &[]), "on my way home");
+        assert!(client.read_cache().unwrap().transition);
+
+        // Back at the office, then home.
+        run(&office, &[]);
+        assert!(!client.read_cache().unwrap().transition);
+        assert_eq!(run(&home, &[]), "home early");
+        let cache = client.read_cache().unwrap();
+        assert_eq!((cache.location.as_deref(), cache.transition), (Some("home"), true));
+
+        // A rule wins over the transition, its status is not kept as one once
+        // the rule does not match anymore.
+        config.rules = toml::from_str::<Config>(r#"
+            token = "xxx"
+            ignore_ips = []
+            locations = []
+
+            [[rules]]
+            when = 'location == "home" && meeting'
+            text = "in a call from home"
+            emoji = ":telephone_receiver:"
+        "#).unwrap().rules;
+        let client = SlackStatus::with_cache_file(&config, cache_file(&dir)).unwrap();
+        let run = |detection: &Detection, meetings: &[Meeting]| {
+            let (status, source) = client.status_with(detection, meetings, chrono::Utc::now());
+            client.remember_location(detection, source);
+            let cache = Cache { status: StatusCache { text: status.text.clone(), ..Default::default() }, ..client.read_cache().unwrap() };
+            cache.save_to(&cache_file(&dir)).unwrap();
+            status.text
+        };
+        let now = chrono::Utc::now();
+        let call = Meeting {
+            summary: "Call".to_string(),
+            start: now - chrono::Duration::minutes(10),
+            end: now + chrono::Duration::minutes(20),
+        };
+
+        run(&office, &[]);
+        assert_eq!(run(&home, &[call]), "in a call from home");
+        assert!(!client.read_cache().unwrap().transition);
+        assert_eq!(run(&home, &[]), "working remotely");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn schedule_config() -> Config {
+        toml::from_str(r#"
+            token = "xxx"
+            ignore_ips = []
+
+            [[locations]]
+            name = "office"
+            ip = "203.0.113.16/28"
+            text = "at the office"
+            emoji = ":office:"
+
+            [[locations]]
+            name = "home"
+            ip = "198.51.100.0/24"
+            text = "working remotely"
+            emoji = ":house_with_garden:"
+
+            [schedule]
+            timezone = "Europe/Paris"
+
+            [[schedule.rules]]
+            days = ["sat", "sun"]
+            text = "weekend"
+            emoji = ":palm_tree:"
+
+            [[schedule.rules]]
+            days = ["mon-fri"]
+            from = "12:00"
+            to = "13:30"
+            text = "lunch"
+            emoji = ":fork_and_knife:"
+
+            [[schedule.rules]]
+            days = ["fri"]
+            location = "home"
+            text = "remote Friday"
+            emoji = ":house_with_garden:"
+
+            [[schedule.rules]]
+            from = "18:00"
+            to = "09:00"
+            text = "off for the day"
+            emoji = ":zzz:"
+        "#).unwrap()
+    }
+
+    #[test]
+    fn test_schedule_rules() {
+        use chrono::{TimeZone, Utc};
+
+        let config = schedule_config();
+        assert!(config.check().is_ok());
+        let schedule = config.schedule.unwrap();
+        let at = |d, h, m| Utc.with_ymd_and_hms(2026, 10, d, h, m, 0).unwrap();
+
+        // Friday 12:30 in Paris (UTC+2), until 13:30.
+        let status = schedule.status_at(at(16, 10, 30), "office").unwrap();
+        assert_eq!(status.text, "lunch");
+        assert_eq!(status.expiration, Some(at(16, 11, 30).timestamp()));
+
+        // Friday 10:00, only at home.
+        assert!(schedule.status_at(at(16, 8, 0), "office").is_none());
+        let status = schedule.status_at(at(16, 8, 0), "home").unwrap();
+        assert_eq!(status.text, "remote Friday");
+        assert_eq!(status.expiration, Some(at(16, 22, 0).timestamp()));
+
+        // Thursday 19:00, until Friday 09:00.
+        let status = schedule.status_at(at(15, 17, 0), "unknown").unwrap();
+        assert_eq!(status.text, "off for the day");
+        assert_eq!(status.expiration, Some(at(16, 7, 0).timestamp()));
+        assert_eq!(schedule.status_at(at(16, 6, 59), "office").unwrap().text, "off for the day");
+
+        // Saturday, the whole day.
+        assert_eq!(schedule.status_at(at(17, 8, 0), "office").unwrap().text, "weekend");
+    }
+
+    #[test]
+    fn test_schedule_over_location() {
+        use chrono::{TimeZone, Utc};
+
+        let config = schedule_config();
+        let client = SlackStatus::from(&config).unwrap();
+        let office = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+
+        let status = client.status_at(&office, Utc.with_ymd_and_hms(2026, 10, 16, 8, 0, 0).unwrap());
+        assert_eq!(status.text, "at the office");
+        let status = client.status_at(&office, Utc.with_ymd_and_hms(2026, 10, 16, 10, 0, 0).unwrap());
+        assert_eq!(status.text, "lunch");
+    }
+
+    #[test]
+    fn test_config_check_schedule() {
+        let mut config = schedule_config();
+        let schedule = config.schedule.as_mut().unwrap();
+        schedule.timezone = Some("Europe/Lutece".to_string());
+        schedule.rules[0].days = Some(vec!["someday".to_string()]);
+        schedule.rules[1].to = Some("25:00".to_string());
+        schedule.rules[2].location = Some("beach".to_string());
+
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("schedule: Unknown timezone: Europe/Lutece"));
+        assert!(error.contains("schedule rule \"weekend\": invalid day of week: someday"));
+        assert!(error.contains("schedule rule \"lunch\": invalid time (HH:MM): 25:00"));
+        assert!(error.contains("schedule rule \"remote Friday\" refers to unknown location \"beach\""));
+    }
+
+    #[test]
+    fn test_ooo() {
+        use chrono::{TimeZone, Utc};
+
+        let mut config = schedule_config();
+        config.ooo.push(toml::from_str(r#"
+            from = "2026-12-21"
+            to = "2027-01-01"
+        "#).unwrap());
+        assert!(config.check().is_ok());
+
+        let client = SlackStatus::from(&config).unwrap();
+        let office = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+
+        // Midnight in Paris (UTC+1) until midnight after the last day, over
+        // schedule rules.
+        let status = client.status_at(&office, Utc.with_ymd_and_hms(2026, 12, 20, 23, 0, 0).unwrap());
+        assert_eq!(status.text, "out of office");
+        assert_eq!(status.emoji, ":palm_tree:");
+        assert_eq!(status.expiration, Some(Utc.with_ymd_and_hms(2027, 1, 1, 23, 0, 0).unwrap().timestamp()));
+        assert!(client.ooo_status(Utc.with_ymd_and_hms(2026, 12, 20, 22, 59, 0).unwrap()).is_none());
+        assert!(client.ooo_status(Utc.with_ymd_and_hms(2027, 1, 1, 22, 59, 0).unwrap()).is_some());
+        assert!(client.ooo_status(Utc.with_ymd_and_hms(2027, 1, 1, 23, 0, 0).unwrap()).is_none());
+
+        config.ooo[0].to = chrono::NaiveDate::from_ymd_opt(2026, 12, 1).unwrap();
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("out-of-office range from 2026-12-21 to 2026-12-01 ends before it starts"));
+    }
+
+    const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:standup\r
+SUMMARY:Standup\r
+DTSTART;TZID=Europe/Paris:20261005T093000\r
+DTEND;TZID=Europe/Paris:20261005T094500\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR;UNTIL=20261231T235959\r
+EXDATE;TZID=Europe/Paris:20261014T093000\r
+BEGIN:VALARM\r
+TRIGGER:-PT5M\r
+DTSTART:20000101T000000Z\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup\r
+RECURRENCE-ID;TZID=Europe/Paris:20261015T093000\r
+SUMMARY:Standup (moved)\r
+DTSTART;TZID=Europe/Paris:20261015T110000\r
+DTEND;TZID=Europe/Paris:20261015T113000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:holiday\r
+SUMMARY:Holiday\r
+DTSTART;VALUE=DATE:20261016\r
+DTEND;VALUE=DATE:20261017\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:focus\r
+SUMMARY:Focus time\r
+DTSTART:20261016T140000Z\r
+DTEND:20261016T160000Z\r
+TRANSP:TRANSPARENT\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:review\r
+SUMMARY:Quarterly review with a very long title, folded over \r
+ several lines\r
+DTSTART:20261016T120000Z\r
+DURATION:PT1H30M\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn test_ics_meetings() {
+        use chrono::{TimeZone, Utc};
+
+        let ics = Ics::parse(ICS, None).unwrap();
+        let at = |m, d, h, min| Utc.with_ymd_and_hms(2026, m, d, h, min, 0).unwrap();
+
+        // Paris is UTC+2 until October 25th, UTC+1 after.
+        let meeting = ics.meeting_at(at(10, 13, 7, 40)).unwrap();
+        assert_eq!(meeting.summary, "Standup");
+        assert_eq!(meeting.end, at(10, 13, 7, 45));
+        assert!(ics.meeting_at(at(10, 13, 7, 45)).is_none());
+        assert_eq!(ics.meeting_at(at(10, 26, 8, 30)).unwrap().end, at(10, 26, 8, 45));
+        assert!(ics.meeting_at(at(10, 26, 7, 30)).is_none());
+
+        // Excluded, moved and weekend occurrences.
+        assert!(ics.meeting_at(at(10, 14, 7, 30)).is_none());
+        assert!(ics.meeting_at(at(10, 15, 7, 30)).is_none());
+        assert_eq!(ics.meeting_at(at(10, 15, 9, 0)).unwrap().summary, "Standup (moved)");
+        assert!(ics.meeting_at(at(10, 17, 7, 30)).is_none());
+        assert!(ics.meeting_at(at(12, 31, 8, 30)).is_some());
+        assert!(ics.meeting_at(Utc.with_ymd_and_hms(2027, 1, 4, 8, 30, 0).unwrap()).is_none());
+
+        // All-day and free events are not meetings.
+        assert!(ics.meeting_at(at(10, 16, 10, 0)).is_none());
+        assert!(ics.meeting_at(at(10, 16, 15, 0)).is_none());
+        let meeting = ics.meeting_at(at(10, 16, 13, 0)).unwrap();
+        assert_eq!(meeting.summary, "Quarterly review with a very long title, folded over several lines");
+        assert_eq!(meeting.end, at(10, 16, 13, 30));
+
+        assert!(Ics::parse("BEGIN:VCARD\r\nEND:VCARD\r\n", None).is_err());
+
+        // Long-running series are expanded around the lookup only.
+        let ics = Ics::parse("BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+SUMMARY:Check-in\r
+DTSTART;TZID=Europe/Paris:20000103T100000\r
+DURATION:PT15M\r
+RRULE:FREQ=MINUTELY;INTERVAL=30\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Sync\r
+DTSTART;TZID=Europe/Paris:20000103T140000\r
+DURATION:PT1H\r
+RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR\r
+END:VEVENT\r
+END:VCALENDAR\r
+", None).unwrap();
+        assert_eq!(ics.meeting_at(at(10, 16, 8, 10)).unwrap().end, at(10, 16, 8, 15));
+        assert!(ics.meeting_at(at(10, 16, 8, 20)).is_none());
+        // Every other week, 1397 weeks after the first one is off.
+        assert_eq!(ics.meeting_at(at(10, 16, 12, 30)).unwrap().summary, "Check-in");
+        assert_eq!(ics.meeting_at(at(10, 19, 12, 30)).unwrap().summary, "Sync");
+        assert_eq!(ics.meeting_at(at(10, 23, 12, 30)).unwrap().summary, "Sync");
+        assert_eq!(ics.meeting_at(at(10, 26, 13, 30)).unwrap().summary, "Check-in");
+        assert_eq!(ics.meeting_at(at(11, 2, 13, 30)).unwrap().summary, "Sync");
+        // Every check-in of the day, none of the previous 26 years.
+        assert_eq!(ics.meetings(at(10, 16, 0, 0), at(10, 17, 0, 0)).len(), 49);
+        // Expansions stop at MAX_OCCURRENCES, however long the lookup.
+        let year = ics.meetings(at(1, 1, 0, 0), at(12, 31, 0, 0));
+        assert!(year.iter().filter(|m| m.summary == "Check-in").count() <= calendar::MAX_OCCURRENCES as usize);
+    }
+
+    #[test]
+    fn test_meeting_over_location() {
+        use chrono::{TimeZone, Utc};
+
+        let path = std::env::temp_dir().join(format!("slack-status-calendar-{}.ics", std::process::id()));
+        std::fs::write(&path, ICS).unwrap();
+        let mut config = schedule_config();
+        config.calendar = Some(toml::from_str(&format!("ics = {:?}", path)).unwrap());
+        assert!(config.check().is_ok());
+
+        let client = SlackStatus::from(&config).unwrap();
+        let office = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+        let status = client.status_at(&office, Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap());
+        assert_eq!(status.text, "In a meeting");
+        assert_eq!(status.emoji, ":spiral_calendar_pad:");
+        assert_eq!(status.expiration, Some(Utc.with_ymd_and_hms(2026, 10, 16, 13, 30, 0).unwrap().timestamp()));
+
+        // Back to schedule and location statuses once it ends.
+        let status = client.status_at(&office, Utc.with_ymd_and_hms(2026, 10, 16, 13, 30, 0).unwrap());
+        assert_eq!(status.text, "at the office");
+
+        config.calendar.as_mut().unwrap().ics = Some("/nonexistent.ics".to_string());
+        assert!(config.check().unwrap_err().to_string().contains("calendar: Cannot read calendar /nonexistent.ics"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    const MULTISTATUS: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <d:response>
+    <d:href>/calendars/alice/work/standup.ics</d:href>
+    <d:propstat>
+      <d:prop>
+        <cal:calendar-data>BEGIN:VCALENDAR&#13;
+VERSION:2.0&#13;
+BEGIN:VEVENT&#13;
+UID:rd&#13;
+SUMMARY:R&amp;D sync&#13;
+DTSTART;TZID=Europe/Paris:20261005T140000&#13;
+DTEND;TZID=Europe/Paris:20261005T150000&#13;
+RRULE:FREQ=WEEKLY;BYDAY=FR&#13;
+END:VEVENT&#13;
+END:VCALENDAR&#13;
+</cal:calendar-data>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/calendars/alice/work/review.ics</d:href>
+    <d:propstat>
+      <d:prop>
+        <cal:calendar-data><![CDATA[BEGIN:VCALENDAR
+VERSION:2.0
+BEGIN:VEVENT
+UID:review
+SUMMARY:Review
+DTSTART:20261016T150000Z
+DTEND:20261016T160000Z
+END:VEVENT
+END:VCALENDAR
+]]></cal:calendar-data>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>
+"#;
+
+    /// CalDAV server stand-in answering calendar-query REPORTs with the same
+    /// events when authorized, returns its URL.
+    fn serve_caldav(authorization: &'static str) -> String {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/calendars/alice/work/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+
+                // Read headers and body.
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                loop {
+                    let n = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                    let text = String::from_utf8_lossy(&request).to_lowercase();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text.lines()
+                            .find_map(|l| l.strip_prefix("content-length: "))
+                            .map_or(0, |l| l.trim().parse().unwrap());
+                        if n == 0 || request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+
+                let request = String::from_utf8_lossy(&request).to_string();
+                let authorized = request.lines()
+                    .any(|l| l.eq_ignore_ascii_case(&format!("authorization: {}", authorization)));
+                let (status, body) = if !request.starts_with("REPORT /calendars/alice/work/ ") {
+                    ("405 Method Not Allowed", "")
+                } else if !authorized {
+                    ("401 Unauthorized", "")
+                } else if request.to_lowercase().contains("depth: 1")
+                    && request.contains("<C:time-range start=\"20261015T120000Z\" end=\"20261023T120000Z\"/>")
+                {
+                    ("207 Multi-Status", MULTISTATUS)
+                } else {
+                    ("400 Bad Request", "")
+                };
+                let _ = write!(stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status, body.len(), body);
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_caldav() {
+        use chrono::{TimeZone, Utc};
+
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+        let caldav = CalDav {
+            url: serve_caldav("Basic YWxpY2U6c2VjcmV0"),
+            username: Some("alice".to_string()),
+            password: Some("secret".to_string()),
+            token: None,
+            days: None,
+        };
+        assert!(caldav.check().is_ok());
+
+        let ics = Ics::parse(&caldav.sync(now).unwrap(), None).unwrap();
+        let meetings = ics.meetings(now, now + chrono::Duration::days(1));
+        assert_eq!(meetings.len(), 2);
+        assert_eq!(meetings[0].summary, "R&D sync");
+        assert_eq!(meetings[0].start, Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap());
+        assert_eq!(meetings[1].summary, "Review");
+        assert_eq!(ics.meeting_at(now).unwrap().end, Utc.with_ymd_and_hms(2026, 10, 16, 13, 0, 0).unwrap());
+
+        let wrong = CalDav { password: Some("wrong".to_string()), ..caldav.clone() };
+        assert!(wrong.sync(now).unwrap_err().to_string().contains("401"));
+
+        let bearer = CalDav {
+            url: serve_caldav("Bearer t0ken"),
+            username: None,
+            password: None,
+            token: Some("t0ken".to_string()),
+            days: None,
+        };
+        assert_eq!(Ics::parse(&bearer.sync(now).unwrap(), None).unwrap().meetings(now, now).len(), 1);
+
+        // No events.
+        let empty = caldav::parse_multistatus(r#"<d:multistatus xmlns:d="DAV:"/>"#).unwrap();
+        assert!(Ics::parse(&empty, None).unwrap().meeting_at(now).is_none());
+
+        let both = CalDav { token: Some("t0ken".to_string()), ..caldav };
+        assert!(both.check().is_err());
+    }
+
+    #[test]
+    fn test_templates() {
+        use chrono::{FixedOffset, TimeZone, Utc};
+
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 8, 5, 0).unwrap();
+        let paris = FixedOffset::east_opt(2 * 3600).unwrap();
+        let variables = Variables {
+            location: "office".to_string(),
+            place: Some(Place { city: None, country: Some("France".to_string()), country_code: Some("FR".to_string()) }),
+            end_of_day: Some(paris.with_ymd_and_hms(2026, 10, 16, 18, 0, 0).unwrap()),
+            ..Variables::at(now, Some(chrono_tz::Europe::Paris))
+        };
+        let render = |t: &str| Template::parse(t).unwrap().render(&variables);
+
+        assert_eq!(render("In {location} ({local_time} {tz})"), "In office (10:05 CEST)");
+        assert_eq!(render("Working from {city} {flag}"), "Working from France :flag-fr:");
+        assert_eq!(render("Until {end_of_day:%Hh}, {{literally}}"), "Until 18h, {literally}");
+        assert_eq!(render("Back at {expiration}"), "Back at ");
+
+        let error = |t: &str| Template::parse(t).err().unwrap();
+        assert!(error("{town}").starts_with("unknown variable {town}"));
+        assert_eq!(error("{city:%H}"), "{city} is not a time, it has no format");
+        assert_eq!(error("{expiration:%Q}"), "invalid time format \"%Q\" for {expiration}");
+        assert_eq!(error("At {city"), "unclosed variable {city");
+        assert_eq!(error("smile :)}"), "unmatched }, write }} for a brace");
+    }
+
+    #[test]
+    fn test_status_templates() {
+        use chrono::{TimeZone, Utc};
+
+        let mut config = schedule_config();
+        config.locations[0].text = "In {location} ({local_time} {tz}) until {end_of_day}".to_string();
+        let schedule = config.schedule.as_mut().unwrap();
+        schedule.end_of_day = Some("17:30".to_string());
+        schedule.rules[1].text = "lunch, back at {expiration}".to_string();
+        assert!(config.check().is_ok());
+
+        let client = SlackStatus::from(&config).unwrap();
+        let office = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+        let status = client.status_at(&office, Utc.with_ymd_and_hms(2026, 10, 16, 8, 0, 0).unwrap());
+        assert_eq!(status.text, "In office (10:00 CEST) until 17:30");
+        let status = client.status_at(&office, Utc.with_ymd_and_hms(2026, 10, 16, 10, 0, 0).unwrap());
+        assert_eq!(status.text, "lunch, back at 13:30");
+
+        // Invalid templates are reported when loading configuration.
+        config.locations[1].emoji = "{flag:%H}".to_string();
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("location \"home\" emoji template: {flag} is not a time, it has no format"));
+
+        let path = std::env::temp_dir().join(format!("slack-status-templates-{}.toml", std::process::id()));
+        std::fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+        let error = Config::read(path.to_str()).err().unwrap().to_string();
+        assert!(error.contains("location \"home\" emoji template"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rule_conditions() {
+        use chrono::{FixedOffset, TimeZone};
+
+        let signals = |d, h, m| Signals {
+            location: "office".to_string(),
+            ips: vec![IpAddr::from_str("10.1.2.3").unwrap()],
+            ssid: Some("corp".to_string()),
+            place: None,
+            vpn: false,
+            ignored: false,
+            meeting: true,
+            now: FixedOffset::east_opt(7200).unwrap().with_ymd_and_hms(2026, 10, d, h, m, 0).unwrap(),
+        };
+        let eval = |c: &str, s: &Signals| Condition::from_str(c).unwrap().eval(s);
+
+        // Friday 10:00.
+        let friday = signals(16, 10, 0);
+        assert!(eval(r#"location == "office" && weekday && time in 09:00-18:00"#, &friday));
+        assert!(eval(r#"ip in "10.0.0.0/8" && ssid != "guest""#, &friday));
+        assert!(eval(r#"!(day in ["sat", "sun"]) || vpn"#, &friday));
+        assert!(eval("meeting && time >= 09:30 && time < 10:01", &friday));
+        assert!(!eval(r#"ip in "192.168.0.0/16" || weekend"#, &friday));
+        // Ranges may span midnight.
+        assert!(!eval("time in 22:00-06:00", &friday));
+        assert!(eval("time in 22:00-06:00", &signals(17, 23, 30)));
+        assert!(eval(r#"day == "sat" && weekend"#, &signals(17, 23, 30)));
+
+        let error = |c: &str| Condition::from_str(c).unwrap_err().to_string();
+        assert!(error("locaton == \"office\"").contains("unknown signal \"locaton\""));
+        assert!(error("location == 09:00").contains("a string"));
+        assert!(error("location").contains("a boolean"));
+        assert!(error("weekday &&").contains("the end"));
+        assert!(error("time in 09:00").contains("invalid condition"));
+
+        // Deep nesting is an error, not a stack overflow.
+        let nested = |n| format!("{}weekday{}", "(".repeat(n), ")".repeat(n));
+        assert!(eval(&nested(60), &friday));
+        assert!(error(&nested(100_000)).contains("nested too deeply"));
+        assert!(error(&format!("{}weekday", "!".repeat(100_000))).contains("nested too deeply"));
+        assert!(error(&vec!["weekday"; 100].join(" || ")).contains("nested too deeply"));
+    }
+
+    #[test]
+    fn test_rules_over_schedule() {
+        use chrono::{TimeZone, Utc};
+
+        let mut config = schedule_config();
+        config.rules = toml::from_str::<Config>(r#"
+            token = "xxx"
+            ignore_ips = []
+            locations = []
+
+            [[rules]]
+            name = "focus"
+            when = 'location == "office" && day == "fri" && time in 09:00-12:00'
+            text = "focus time, back at {expiration}"
+            emoji = ":headphones:"
+            until = "12:00"
+            presence = "away"
+            dnd_minutes = 30
+
+            [[rules]]
+            when = "weekend"
+            presence = "away"
+        "#).unwrap().rules;
+        assert!(config.check().is_ok());
+
+        let client = SlackStatus::from(&config).unwrap();
+        let office = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+        let home = Detection { location: Some(config.locations[1].clone()), ..Default::default() };
+        let at = |d, h, m| Utc.with_ymd_and_hms(2026, 10, d, h, m, 0).unwrap();
+
+        // Friday 10:00 in Paris, the rule wins over the location.
+        let status = client.status_at(&office, at(16, 8, 0));
+        assert_eq!(status.text, "focus time, back at 12:00");
+        assert_eq!(status.expiration, Some(at(16, 10, 0).timestamp()));
+        let rule = client.matching_rule(&office, &[], at(16, 8, 0)).unwrap();
+        assert_eq!(rule.presence, Some(Presence::Away));
+        assert_eq!(rule.dnd_minutes, Some(30));
+        // Not at the office, the schedule applies.
+        assert!(client.matching_rule(&home, &[], at(16, 8, 0)).is_none());
+        assert_eq!(client.status_at(&home, at(16, 8, 0)).text, "remote Friday");
+        // Rules without status only set presence.
+        assert_eq!(client.matching_rule(&home, &[], at(17, 8, 0)).unwrap().label(), "weekend");
+        assert_eq!(client.status_at(&home, at(17, 8, 0)).text, "weekend");
+
+        // Invalid conditions and times are reported when loading configuration.
+        let error = toml::from_str::<Rule>(r#"when = "vpn && city""#).unwrap_err().to_string();
+        assert!(error.contains("invalid condition"));
+        config.rules[0].until = Some("noon".to_string());
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("rule \"focus\": until: invalid time"));
+    }
+
+    /// Write a script in a test directory, returns its path.
+    fn write_script(dir: &std::path::Path, name: &str, source: &str) -> String {
+        let path = dir.join(format!("{}.rhai", name));
+        std::fs::write(&path, source).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_script_status() {
+        use chrono::{TimeZone, Utc};
+
+        let mut config = schedule_config();
+        let dir = test_dir("script");
+        config.script = Some(Script {
+            path: write_script(&dir, "status", r#"
+                if ctx.location == "office" && ctx.day == "fri" && ctx.time >= "16:00" {
+                    #{ text: "beer o'clock, until {expiration}", emoji: ":beer:", expiration: ctx.timestamp + 3600 }
+                } else if ctx.location == "home" {
+                    #{ text: ctx.status.text + "!", emoji: ctx.status.emoji }
+                } else if ctx.location == "unknown" && type_of(ctx.cached) == "map" {
+                    #{ text: "still " + ctx.cached.text, emoji: ctx.cached.emoji }
+                }
+            "#),
+            timeout_ms: None,
+        });
+        assert!(config.check().is_ok());
+
+        let client = SlackStatus::with_cache_file(&config, cache_file(&dir)).unwrap();
+        let office = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+        let home = Detection { location: Some(config.locations[1].clone()), ..Default::default() };
+        let at = |d, h, m| Utc.with_ymd_and_hms(2026, 10, d, h, m, 0).unwrap();
+
+        // Friday 16:30 in Paris.
+        let status = client.status_at(&office, at(16, 14, 30));
+        assert_eq!(status.text, "beer o'clock, until 17:30");
+        assert_eq!(status.expiration, Some(at(16, 15, 30).timestamp()));
+        // The script sees the built-in status.
+        assert_eq!(client.status_at(&home, at(16, 14, 30)).text, "remote Friday!");
+        // () keeps the built-in status.
+        assert_eq!(client.status_at(&office, at(16, 10, 30)).text, "lunch");
+
+        // The script sees the status last set.
+        let status = StatusCache { text: "at the office".to_string(), emoji: ":office:".to_string(), expiration: 0 };
+        Cache { status, ..Default::default() }.save_to(&cache_file(&dir)).unwrap();
+        assert_eq!(client.status_at(&Detection::default(), at(16, 14, 30)).text, "still at the office");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_script_failures() {
+        use chrono::{TimeZone, Utc};
+
+        let mut config = schedule_config();
+        let dir = test_dir("script-failures");
+        let client_with = |config: &mut Config, name, source| {
+            config.script = Some(Script { path: write_script(&dir, name, source), timeout_ms: Some(50) });
+            let client = SlackStatus::with_cache_file(config, cache_file(&dir)).unwrap();
+            let office = Detection { location: Some(config.locations[0].clone()), ..Default::default() };
+            client.status_at(&office, Utc.with_ymd_and_hms(2026, 10, 16, 10, 30, 0).unwrap()).text
+        };
+
+        // Scripts running too long are stopped.
+        let start = std::time::Instant::now();
+        assert_eq!(client_with(&mut config, "loop", "loop { }"), "lunch");
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        // Errors, invalid statuses and sandbox escapes fall back to the built-in status.
+        assert_eq!(client_with(&mut config, "error", r#"throw "oops""#), "lunch");
+        assert_eq!(client_with(&mut config, "invalid", "#{ text: 42 }"), "lunch");
+        assert_eq!(client_with(&mut config, "string", r#""busy""#), "lunch");
+        assert_eq!(client_with(&mut config, "import", r#"import "secrets" as s; #{ text: s::text }"#), "lunch");
+        assert_eq!(client_with(&mut config, "eval", r#"eval("40 + 2")"#), "lunch");
+
+        // Syntax errors are reported when loading configuration.
+        config.script = Some(Script { path: write_script(&dir, "syntax", "if {"), timeout_ms: None });
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("Invalid script"));
+        config.script = Some(Script { path: "/nonexistent.rhai".to_string(), timeout_ms: None });
+        assert!(config.check().unwrap_err().to_string().contains("Cannot read script /nonexistent.rhai"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_local_network_locations() {
+        use slack_status::detector::local_network::find_location;
+
+        let locations = vec![
+            Location {
+                name: Some("hq".to_string()),
+                local_network: Some(Network::from_str("10.12.0.0/16").unwrap()),
+                ..Default::default()
+            },
+            Location {
+                name: Some("hq lab".to_string()),
+                local_network: Some(Network::from_str("10.12.40.0/24").unwrap()),
+                ..Default::default()
+            },
+            Location {
+                name: Some("home".to_string()),
+                local_network: Some(Network::from_str("192.168.50.0/24").unwrap()),
+                ..Default::default()
+            },
+        ];
+
+        let ips = vec![IpAddr::from_str("172.17.0.1").unwrap(), IpAddr::from_str("192.168.50.23").unwrap()];
+        assert_eq!(find_location(&locations, &ips).unwrap().label(), "home");
+        let ips = vec![IpAddr::from_str("10.12.40.2").unwrap()];
+        assert_eq!(find_location(&locations, &ips).unwrap().label(), "hq lab");
+        let ips = vec![IpAddr::from_str("10.12.4.2").unwrap()];
+        assert_eq!(find_location(&locations, &ips).unwrap().label(), "hq");
+        assert!(find_location(&locations, &[]).is_none());
+
+        assert!(detector::is_vpn_interface("wg0", &["wg0".to_string()]));
+        assert!(!detector::is_vpn_interface("wlp2s0", &["wg0".to_string()]));
+    }
+
+    #[test]
+    fn test_public_ip_fallback() {
+        let mut config = Config::with("xxx".to_string());
+        config.ip_request_address = vec![unreachable().into(), serve("203.0.113.17").into()];
+        let client = SlackStatus::from(&config).unwrap();
+
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("203.0.113.17").unwrap());
+
+        config.ip_request_address = vec![unreachable().into()];
+        let client = SlackStatus::from(&config).unwrap();
+        assert!(client.get_public_ip().is_err());
+    }
+
+    #[test]
+    fn test_public_ips_dual_stack() {
+        let ipv6 = match serve_ipv6("2001:db8:1::17") {
+            Some(url) => url,
+            None => return,
+        };
+        let mut config = Config::with("xxx".to_string());
+        config.ip_request_address = vec![serve("203.0.113.17").into(), ipv6.into()];
+        let client = SlackStatus::from(&config).unwrap();
+
+        // Each family is requested on its own, the IPv4 service cannot be
+        // reached over IPv6 so the IPv6 one answers.
+        assert_eq!(client.get_public_ips().unwrap(), vec![
+            IpAddr::from_str("203.0.113.17").unwrap(),
+            IpAddr::from_str("2001:db8:1::17").unwrap(),
+        ]);
+
+        // An answer of the other family is rejected.
+        config.ip_request_address = vec![serve("2001:db8:1::17").into()];
+        let client = SlackStatus::from(&config).unwrap();
+        assert!(client.get_public_ips().is_err());
+        assert!(client.get_public_ip().is_ok());
+    }
+
+    #[test]
+    fn test_public_ip_request_source() {
+        let ipv6 = match serve_ipv6("2001:db8:1::17") {
+            Some(url) => url,
+            None => return,
+        };
+        let mut config = Config::with("xxx".to_string());
+        config.ip_request_address = vec![serve("203.0.113.17").into(), ipv6.into()];
+
+        // Requests are sent from this address only, over its family.
+        config.ip_request_source = Some("::1".to_string());
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("2001:db8:1::17").unwrap());
+        assert_eq!(client.get_public_ips().unwrap(), vec![IpAddr::from_str("2001:db8:1::17").unwrap()]);
+
+        config.ip_request_source = Some("127.0.0.1".to_string());
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("203.0.113.17").unwrap());
+
+        // Requests are sent through the interface, from its address of each
+        // family.
+        config.ip_request_source = Some("lo".to_string());
+        assert!(config.check().is_ok());
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("203.0.113.17").unwrap());
+        assert_eq!(client.get_public_ips().unwrap().len(), 2);
+
+        config.ip_request_source = Some("nonexistent0".to_string());
+        let client = SlackStatus::from(&config).unwrap();
+        assert!(client.get_public_ip().unwrap_err().to_string().contains("No interface nonexistent0"));
+        config.ip_request_source = Some("not an interface".to_string());
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn test_http_answers() {
+        use slack_status::ip_lookup::http::parse_http;
+
+        let (status, body) = parse_http(b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\n203.0.113.17").unwrap();
+        assert_eq!((status, body.as_str()), (200, "203.0.113.17"));
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n7\r\n203.0.1\r\n5;x=y\r\n13.17\r\n0\r\n\r\n";
+        assert_eq!(parse_http(chunked).unwrap().1, "203.0.113.17");
+        assert_eq!(parse_http(b"HTTP/1.1 404 Not Found\r\n\r\n").unwrap().0, 404);
+        assert!(parse_http(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_http(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n").is_err());
+    }
+
+    #[test]
+    fn test_status_from_location_dual_stack() {
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            ignore_ips = []
+
+            [[locations]]
+            name = "office"
+            ip = "203.0.113.16/28"
+            ip6 = "2001:db8:1::/64"
+            text = "at the office"
+            emoji = ":office:"
+
+            [[locations]]
+            name = "home"
+            ip = "198.51.100.0/24"
+            text = "working remotely"
+            emoji = ":house_with_garden:"
+        "#).unwrap();
+        let client = SlackStatus::from(&config).unwrap();
+
+        for ip in &["203.0.113.17", "2001:db8:1::abcd"] {
+            let status = client.status_from_location(&IpAddr::from_str(ip).unwrap()).unwrap();
+            assert_eq!(status.text, "at the office");
+        }
+
+        // Networks of both families are compared by their number of host bits.
+        let mut a = location("a", "198.51.100.0/24", None);
+        let mut b = location("b", "198.51.100.0/24", None);
+        b.ip6 = Some(Network::from_str("2001:db8:1::/124").unwrap());
+        assert_eq!(a.precedence(&b), std::cmp::Ordering::Less);
+        a.ip6 = Some(Network::from_str("2001:db8:1::/126").unwrap());
+        assert_eq!(a.precedence(&b), std::cmp::Ordering::Greater);
+    }
+
+    #[test]
+    fn test_public_ip_quorum() {
+        let mut config = Config::with("xxx".to_string());
+        config.ip_quorum = Some(2);
+        config.ip_request_address = vec![
+            serve("203.0.113.17").into(),
+            unreachable().into(),
+            serve("198.51.100.1").into(),
+            serve("203.0.113.17").into(),
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("203.0.113.17").unwrap());
+        assert!(config.check().is_ok());
+
+        config.ip_request_address.truncate(3);
+        let client = SlackStatus::from(&config).unwrap();
+        let error = client.get_public_ip().unwrap_err().to_string();
+        assert!(error.contains("Less than 2 services agree"));
+
+        // Answers are listed by IP.
+        let answers = vec![
+            ("a", Ok(IpAddr::from_str("203.0.113.17").unwrap())),
+            ("b", Ok(IpAddr::from_str("198.51.100.1").unwrap())),
+            ("c", Ok(IpAddr::from_str("203.0.113.2").unwrap())),
+        ];
+        let error = ip_lookup::agree(answers.into_iter(), 2).unwrap_err().to_string();
+        assert_eq!(error, "Less than 2 services agree on public IP \
+            (198.51.100.1 from b; 203.0.113.2 from c; 203.0.113.17 from a)");
+
+        config.ip_quorum = Some(4);
+        assert!(config.check().is_err());
+        config.ip_quorum = None;
+        config.ip_request_timeout = Some(0);
+        assert!(config.check().unwrap_err().to_string().contains("ip_request_timeout must be at least 1 second"));
+    }
+
+    #[test]
+    fn test_public_ip_quorum_stops_asking() {
+        let answers = vec![
+            ("a", Ok(IpAddr::from_str("203.0.113.17").unwrap())),
+            ("b", Ok(IpAddr::from_str("203.0.113.17").unwrap())),
+        ];
+        let mut asked = 0;
+        let ip = ip_lookup::agree(answers.into_iter().inspect(|_| asked += 1), 1).unwrap();
+
+        assert_eq!(ip, IpAddr::from_str("203.0.113.17").unwrap());
+        assert_eq!(asked, 1);
+    }
+
+    #[test]
+    fn test_config_ip_request_address() {
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            ip_request_address = "https://ip.clara.net"
+            ignore_ips = []
+            locations = []
+        "#).unwrap();
+        assert_eq!(config.ip_request_address, vec![IpService::Url("https://ip.clara.net".to_string())]);
+
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            ip_request_address = ["https://ip.clara.net", "https://api.ipify.org"]
+            ip_quorum = 2
+            ignore_ips = []
+            locations = []
+        "#).unwrap();
+        assert_eq!(config.ip_request_address.len(), 2);
+        assert!(config.check().is_ok());
+    }
+
+    #[test]
+    fn test_public_ip_response_formats() {
+        use slack_status::ip_lookup::{HttpService, ResponseFormat};
+
+        let mut config = Config::with("xxx".to_string());
+        config.ip_request_address = vec![
+            IpService::Http(HttpService {
+                url: serve("{\"ip\": \"203.0.113.17\"}"),
+                format: ResponseFormat::Json,
+                field: None,
+                regex: None,
+            }),
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("203.0.113.17").unwrap());
+
+        config.ip_request_address = vec![serve("2001:db8::17\n").into()];
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("2001:db8::17").unwrap());
+
+        let service = HttpService {
+            url: "https://example.com".to_string(),
+            format: ResponseFormat::Json,
+            field: Some("data.addresses.1".to_string()),
+            regex: None,
+        };
+        let ip = service.parse_response(r#"{"data": {"addresses": ["::1", "198.51.100.1"]}}"#).unwrap();
+        assert_eq!(ip, IpAddr::from_str("198.51.100.1").unwrap());
+        assert!(service.parse_response(r#"{"data": {}}"#).is_err());
+        assert!(service.parse_response("not json").is_err());
+
+        let service = HttpService {
+            url: "https://example.com".to_string(),
+            format: ResponseFormat::Regex,
+            field: None,
+            regex: Some("Current IP Address: ([0-9.]+)".to_string()),
+        };
+        let ip = service.parse_response("<body>Current IP Address: 198.51.100.1</body>").unwrap();
+        assert_eq!(ip, IpAddr::from_str("198.51.100.1").unwrap());
+        assert!(service.parse_response("<body>Nope</body>").is_err());
+
+        let text = HttpService::text("https://example.com");
+        assert!(text.parse_response("<html>").is_err());
+    }
+
+    #[test]
+    fn test_config_ip_services_round_trip() {
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            ip_request_address = [
+                "https://ip.clara.net",
+                { url = "https://api.ipify.org?format=json", format = "json" },
+                { url = "https://example.com", format = "regex", regex = "IP: (\\S+)" },
+                { resolver = "208.67.222.222", name = "myip.opendns.com" },
+            ]
+            ignore_ips = []
+            locations = []
+        "#).unwrap();
+        assert!(config.check().is_ok());
+
+        let path = std::env::temp_dir().join(format!("slack-status-test-{}.toml", std::process::id()));
+        config.save(path.to_str()).unwrap();
+        let saved = Config::read(path.to_str()).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let services: Vec<_> = saved.ip_request_address.iter().map(|s| s.described()).collect();
+        let expected: Vec<_> = config.ip_request_address.iter().map(|s| s.described()).collect();
+        assert_eq!(services, expected);
+
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            ip_request_address = { url = "https://example.com", format = "regex", regex = "(" }
+            ignore_ips = []
+            locations = []
+        "#).unwrap();
+        assert!(config.check().unwrap_err().to_string().contains("invalid regex"));
+    }
+
+    #[test]
+    fn test_public_ip_dns() {
+        use slack_status::ip_lookup::{DnsService, RecordType};
+
+        let resolver = serve_dns();
+        let mut config = Config::with("xxx".to_string());
+        config.ip_request_address = vec![
+            IpService::Dns(DnsService {
+                resolver: resolver.clone(),
+                name: "myip.opendns.com".to_string(),
+                record: RecordType::A,
+            }),
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("203.0.113.17").unwrap());
+
+        config.ip_request_address = vec![
+            IpService::Dns(DnsService {
+                resolver,
+                name: "o-o.myaddr.l.google.com".to_string(),
+                record: RecordType::Txt,
+            }),
+        ];
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("198.51.100.1").unwrap());
+
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            ip_request_address = { resolver = "216.239.32.10", name = "o-o.myaddr.l.google.com", record = "TXT" }
+            ignore_ips = []
+            locations = []
+        "#).unwrap();
+        match &config.ip_request_address[0] {
+            IpService::Dns(s) => assert_eq!(s.record, RecordType::Txt),
+            s => panic!("Not a DNS service: {:?}", s),
+        }
+    }
+
+    #[test]
+    fn test_public_ip_stun() {
+        use slack_status::ip_lookup::StunService;
+
+        let mut config = Config::with("xxx".to_string());
+        config.ip_request_timeout = Some(3);
+        config.ip_request_address = vec![IpService::Stun(StunService { stun: serve_stun() })];
+        let client = SlackStatus::from(&config).unwrap();
+        assert_eq!(client.get_public_ip().unwrap(), IpAddr::from_str("127.0.0.1").unwrap());
+
+        let config: Config = toml::from_str(r#"
+            token = "xxx"
+            ip_request_address = [{ stun = "stun.l.google.com:19302" }, "https://ip.clara.net"]
+            ignore_ips = []
+            locations = []
+        "#).unwrap();
+        assert_eq!(config.ip_request_address[0].describe(), "stun:stun.l.google.com:19302");
+    }
+
+    #[test]
+    fn test_stun_rfc5769_responses() {
+        use slack_status::ip_lookup::stun::parse_response;
+
+        fn hex(s: &str) -> Vec<u8> {
+            let s: String = s.split_whitespace().collect();
+            (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+        }
+
+        let transaction = [0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae];
+        let response = hex("0101003c 2112a442 b7e7a701 bc34d686 fa87dfae
+            8022000b 74657374 20766563 746f7220
+            00200008 0001a147 e112a643
+            00080014 2b91f599 fd9e90c3 8c7489f9 2af9ba53 f06be7d7
+            80280004 c07d4c96");
+        let ip = parse_response(&response, &transaction).unwrap();
+        assert_eq!(ip, IpAddr::from_str("192.0.2.1").unwrap());
+
+        let response = hex("01010048 2112a442 b7e7a701 bc34d686 fa87dfae
+            8022000b 74657374 20766563 746f7220
+            00200014 0002a147 0113a9fa a5d3f179 bc25f4b5 bed2b9d9
+            00080014 a382954e 4be67bf1 1784c97c 8292c275 bfe3ed41
+            80280004 c8fb0b4c");
+        let ip = parse_response(&response, &transaction).unwrap();
+        assert_eq!(ip, IpAddr::from_str("2001:db8:1234:5678:11:2233:4455:6677").unwrap());
+
+        assert!(parse_response(&response, &[0; 12]).is_err());
+    }
 }